@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::instruction::Instruction;
+
+/// includes nested deeper than this are assumed to be a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// an assembly error and where it happened
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: Option<String>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: {}", file, self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// a source line with includes already expanded
+struct SourceLine {
+    file: Option<String>,
+    line: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message: message.into() }
+    }
+}
+
+/// what a line turns into once its label is taken off
+enum Statement {
+    Empty,
+    Constant { name: String, value: String },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Instruction { mnemonic: String, operands: Vec<String> },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Value(u32),
+    //long nnnn, the 16-bit address of XO-CHIP F000 nnnn
+    Long(u32),
+}
+
+/// assembles source text into a rom loaded at 0x200
+/// includes are resolved relative to the working directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand(source, None, Path::new("."), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// assembles a source file, includes are resolved relative to it
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| AsmError { file: Some(name.clone()), line: 0, message: e.to_string() })?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let mut lines = Vec::new();
+    expand(&source, Some(name), &dir, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+/// splits source into lines, replacing include directives with the included file
+fn expand(source: &str, file: Option<String>, dir: &Path, depth: usize, out: &mut Vec<SourceLine>) -> Result<(), AsmError> {
+    for (i, text) in source.lines().enumerate() {
+        let line = SourceLine { file: file.clone(), line: i + 1, text: strip_comment(text).trim().to_string() };
+        let mut words = line.text.splitn(2, char::is_whitespace);
+        if !words.next().unwrap_or("").eq_ignore_ascii_case("include") {
+            out.push(line);
+            continue;
+        }
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(line.error("includes are nested too deeply"));
+        }
+        let name = words.next().unwrap_or("").trim().trim_matches('"');
+        if name.is_empty() {
+            return Err(line.error("include needs a file name"));
+        }
+        let path = dir.join(name);
+        let included = fs::read_to_string(&path).map_err(|e| line.error(format!("cannot include {}: {}", path.display(), e)))?;
+        let included_dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| dir.to_path_buf());
+        expand(&included, Some(path.display().to_string()), &included_dir, depth + 1, out)?;
+    }
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find(';') {
+        Some(i) => &text[..i],
+        None => text
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+/// takes the label off a line and works out what is left
+fn parse_statement(line: &SourceLine) -> Result<(Option<String>, Statement), AsmError> {
+    let mut text = line.text.as_str();
+    let mut label = None;
+    if let Some(i) = text.find(':') {
+        let name = text[..i].trim();
+        if !is_identifier(name) {
+            return Err(line.error(format!("invalid label name '{}'", name)));
+        }
+        label = Some(name.to_string());
+        text = text[i + 1..].trim();
+    }
+    if text.is_empty() {
+        return Ok((label, Statement::Empty));
+    }
+
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, "")
+    };
+    let operands: Vec<String> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(|s| s.trim().to_string()).collect()
+    };
+
+    //NAME equ value
+    if let Some(value) = rest.strip_prefix("equ ").or_else(|| rest.strip_prefix("EQU ")) {
+        if label.is_some() || !is_identifier(head) {
+            return Err(line.error("constants look like NAME equ value"));
+        }
+        return Ok((None, Statement::Constant { name: head.to_string(), value: value.trim().to_string() }));
+    }
+
+    if operands.iter().any(|o| o.is_empty()) {
+        return Err(line.error("empty operand"));
+    }
+    let statement = match head.to_ascii_lowercase().as_str() {
+        "db" => Statement::Bytes(operands),
+        "dw" => Statement::Words(operands),
+        _ => Statement::Instruction { mnemonic: head.to_ascii_uppercase(), operands }
+    };
+    if let Statement::Bytes(ref o) | Statement::Words(ref o) = statement {
+        if o.is_empty() {
+            return Err(line.error(format!("{} needs at least one value", head)));
+        }
+    }
+    Ok((label, statement))
+}
+
+/// first pass places labels and constants, second pass emits bytes
+fn assemble_lines(lines: &[SourceLine]) -> Result<Vec<u8>, AsmError> {
+    let mut statements = Vec::new();
+    let mut symbols: HashMap<String, u32> = HashMap::new();
+    let mut constants = Vec::new();
+    let mut address: u32 = 0x200;
+    for line in lines {
+        let (label, statement) = parse_statement(line)?;
+        if let Some(name) = label {
+            if symbols.insert(name.clone(), address).is_some() {
+                return Err(line.error(format!("'{}' is defined twice", name)));
+            }
+        }
+        address += match &statement {
+            Statement::Empty | Statement::Constant { .. } => 0,
+            Statement::Bytes(values) => values.len() as u32,
+            Statement::Words(values) => 2 * values.len() as u32,
+            Statement::Instruction { operands, .. } => {
+                if operands.iter().any(|o| long_value(o).is_some()) { 4 } else { 2 }
+            },
+        };
+        if let Statement::Constant { name, value } = &statement {
+            constants.push((line, name.clone(), value.clone()));
+        }
+        statements.push((line, statement));
+    }
+
+    //constants may refer to labels and to earlier constants
+    for (line, name, value) in constants {
+        let value = eval(&value, &symbols).map_err(|e| line.error(e))?;
+        if symbols.insert(name.clone(), value).is_some() {
+            return Err(line.error(format!("'{}' is defined twice", name)));
+        }
+    }
+
+    let mut rom = Vec::new();
+    for (line, statement) in statements {
+        match statement {
+            Statement::Empty | Statement::Constant { .. } => {},
+            Statement::Bytes(values) => {
+                for value in values {
+                    let v = eval(&value, &symbols).map_err(|e| line.error(e))?;
+                    rom.push(check(v, 0xFF, "byte").map_err(|e| line.error(e))? as u8);
+                }
+            },
+            Statement::Words(values) => {
+                for value in values {
+                    let v = eval(&value, &symbols).map_err(|e| line.error(e))?;
+                    let v = check(v, 0xFFFF, "word").map_err(|e| line.error(e))?;
+                    rom.push((v >> 8) as u8);
+                    rom.push(v as u8);
+                }
+            },
+            Statement::Instruction { mnemonic, operands } => {
+                let operands = operands.iter()
+                    .map(|o| operand(o, &symbols))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| line.error(e))?;
+                let instruction = build(&mnemonic, &operands).map_err(|e| line.error(e))?;
+                rom.extend(instruction.to_bytes());
+            }
+        }
+    }
+    Ok(rom)
+}
+
+/// a number in decimal, 0x hex or 0b binary, or a symbol
+fn eval(text: &str, symbols: &HashMap<String, u32>) -> Result<u32, String> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u32::from_str_radix(bin, 2).ok()
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        return symbols.get(text).copied().ok_or_else(|| format!("unknown symbol '{}'", text));
+    };
+    parsed.ok_or_else(|| format!("invalid number '{}'", text))
+}
+
+fn check(value: u32, max: u32, what: &str) -> Result<u32, String> {
+    if value > max {
+        return Err(format!("{:#x} does not fit in a {}", value, what));
+    }
+    Ok(value)
+}
+
+/// the value part of a long operand
+fn long_value(text: &str) -> Option<&str> {
+    let (head, rest) = text.split_once(char::is_whitespace)?;
+    if head.eq_ignore_ascii_case("long") { Some(rest.trim()) } else { None }
+}
+
+fn operand(text: &str, symbols: &HashMap<String, u32>) -> Result<Operand, String> {
+    if let Some(value) = long_value(text) {
+        return Ok(Operand::Long(eval(value, symbols)?));
+    }
+    let upper = text.to_ascii_uppercase();
+    let op = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DT,
+        "ST" => Operand::ST,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::HF,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ => {
+            if upper.len() == 2 && upper.starts_with('V') {
+                if let Ok(x) = u8::from_str_radix(&upper[1..], 16) {
+                    return Ok(Operand::V(x));
+                }
+            }
+            Operand::Value(eval(text, symbols)?)
+        }
+    };
+    Ok(op)
+}
+
+/// picks the instruction for a mnemonic and its operands
+fn build(mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+    use Operand::*;
+
+    let addr = |v: u32| check(v, 0xFFF, "12-bit address").map(|v| v as u16);
+    let byte = |v: u32| check(v, 0xFF, "byte").map(|v| v as u8);
+    let nibble = |v: u32| check(v, 0xF, "nibble").map(|v| v as u8);
+
+    let instruction = match (mnemonic, operands) {
+        ("CLS", []) => Instruction::Clear,
+        ("RET", []) => Instruction::Return,
+        ("SCD", [Value(n)]) => Instruction::ScrollDown { n: nibble(*n)? },
+        ("SCU", [Value(n)]) => Instruction::ScrollUp { n: nibble(*n)? },
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("JP", [Value(nnn)]) => Instruction::Jump { nnn: addr(*nnn)? },
+        ("JP", [V(0), Value(nnn)]) => Instruction::JumpV0 { nnn: addr(*nnn)? },
+        ("CALL", [Value(nnn)]) => Instruction::Call { nnn: addr(*nnn)? },
+        ("SE", [V(x), Value(kk)]) => Instruction::SkipEqImm { x: *x, kk: byte(*kk)? },
+        ("SE", [V(x), V(y)]) => Instruction::SkipEqReg { x: *x, y: *y },
+        ("SNE", [V(x), Value(kk)]) => Instruction::SkipNeImm { x: *x, kk: byte(*kk)? },
+        ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg { x: *x, y: *y },
+        ("LD", [V(x), Value(kk)]) => Instruction::LoadImm { x: *x, kk: byte(*kk)? },
+        ("LD", [V(x), V(y)]) => Instruction::Load { x: *x, y: *y },
+        ("LD", [I, Value(nnn)]) => Instruction::LoadI { nnn: addr(*nnn)? },
+        ("LD", [I, Long(nnnn)]) => Instruction::LongLoadI { nnnn: check(*nnnn, 0xFFFF, "16-bit address")? as u16 },
+        ("LD", [V(x), DT]) => Instruction::LoadDelay { x: *x },
+        ("LD", [V(x), K]) => Instruction::WaitKey { x: *x },
+        ("LD", [DT, V(x)]) => Instruction::SetDelay { x: *x },
+        ("LD", [ST, V(x)]) => Instruction::SetSound { x: *x },
+        ("LD", [F, V(x)]) => Instruction::LoadFont { x: *x },
+        ("LD", [HF, V(x)]) => Instruction::LoadBigFont { x: *x },
+        ("LD", [R, V(x)]) => Instruction::StoreFlags { x: *x },
+        ("LD", [V(x), R]) => Instruction::LoadFlags { x: *x },
+        ("LD", [B, V(x)]) => Instruction::StoreBcd { x: *x },
+        ("LD", [IndirectI, V(x)]) => Instruction::StoreRegs { x: *x },
+        ("LD", [V(x), IndirectI]) => Instruction::LoadRegs { x: *x },
+        ("ADD", [V(x), Value(kk)]) => Instruction::AddImm { x: *x, kk: byte(*kk)? },
+        ("ADD", [V(x), V(y)]) => Instruction::Add { x: *x, y: *y },
+        ("ADD", [I, V(x)]) => Instruction::AddI { x: *x },
+        ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => Instruction::SubN { x: *x, y: *y },
+        ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+        ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+        ("RND", [V(x), Value(kk)]) => Instruction::Random { x: *x, kk: byte(*kk)? },
+        ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw { x: *x, y: *y, n: nibble(*n)? },
+        ("SKP", [V(x)]) => Instruction::SkipKeyPressed { x: *x },
+        ("SKNP", [V(x)]) => Instruction::SkipKeyNotPressed { x: *x },
+        ("SAVE", [V(x), V(y)]) => Instruction::SaveRange { x: *x, y: *y },
+        ("LOAD", [V(x), V(y)]) => Instruction::LoadRange { x: *x, y: *y },
+        //only the low two bits select planes, but any nibble assembles so every Fx01 round-trips
+        ("PLANE", [Value(n)]) => Instruction::SelectPlanes { n: nibble(*n)? },
+        ("AUDIO", []) => Instruction::LoadAudio,
+        ("PITCH", [V(x)]) => Instruction::SetPitch { x: *x },
+        _ => return Err(format!("invalid operands for {}", mnemonic))
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError};
+    use crate::disasm::disassemble_rom;
+
+    #[test]
+    fn assembles_labels_constants_and_data() {
+        let source = "
+            SPRITE_H equ 5
+            start:
+                LD I, glyph      ; point at the data
+                DRW V0, V1, SPRITE_H
+            loop: JP loop
+            glyph:
+                db 0xF0, 0b10010000, 144
+                dw 0x90F0
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom, vec![0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04, 0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = assemble("CLS\n\nLD V0, 0x100\n").unwrap_err();
+        assert_eq!(err, AsmError { file: None, line: 3, message: String::from("0x100 does not fit in a byte") });
+        let err = assemble("JP nowhere").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "unknown symbol 'nowhere'");
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        let rom = [0xA2, 0x0C, 0x22, 0x0A, 0x60, 0x01, 0x80, 0x16, 0x12, 0x08, 0x00, 0xEE, 0xF0, 0x90, 0x90];
+        let listing = disassemble_rom(&rom).unwrap();
+        assert_eq!(assemble(&listing).unwrap(), rom);
+
+        // XO-CHIP: LD I, long; a skip over it; PLANE 3; EXIT
+        let rom = [0xF0, 0x00, 0x02, 0x0E, 0x30, 0x00, 0xF0, 0x00, 0x02, 0x0E, 0xF3, 0x01, 0x00, 0xFD, 0xAA];
+        let listing = disassemble_rom(&rom).unwrap();
+        assert!(listing.contains("LD I, long data_20e"));
+        assert_eq!(assemble(&listing).unwrap(), rom);
+
+        // PLANE with bits the machine ignores
+        let rom = [0xF7, 0x01];
+        let listing = disassemble_rom(&rom).unwrap();
+        assert!(listing.contains("PLANE 7"));
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+}
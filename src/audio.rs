@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::Chip8;
+use crate::constants::{DEFAULT_VOLUME, ENVELOPE_TIME, SAMPLES_PER_FRAME, SAMPLE_RATE, TONE_FREQUENCY};
+use crate::error::Chip8Error;
+
+/// shape of the buzzer tone, XO-CHIP patterns always play as they are
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    //A new random level every period, so the frequency still sets how it sounds
+    Noise,
+}
+
+impl Waveform {
+    /// every waveform by name, as --waveform takes them
+    pub const NAMES: [&'static str; 4] = ["square", "sine", "triangle", "noise"];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "noise" => Some(Waveform::Noise),
+            _ => None
+        }
+    }
+}
+
+/// how the buzzer sounds
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+
+    //Amplitude, 1.0 is full scale
+    pub volume: f32,
+}
+
+impl Default for ToneSettings {
+    fn default() -> ToneSettings {
+        ToneSettings { frequency: TONE_FREQUENCY, waveform: Waveform::Square, volume: DEFAULT_VOLUME }
+    }
+}
+
+/// the buzzer's sound: the configured waveform, or an XO-CHIP pattern once one is loaded
+/// it fades in and out over ENVELOPE_TIME when the gate opens and closes, so the sound timer doesn't click
+/// frontends pull samples from it at their own rate
+pub struct Tone {
+    phase_inc: f32,
+    phase: f32,
+    settings: ToneSettings,
+
+    //XO-CHIP pattern, phase then counts bits instead of periods
+    pattern: Option<[u8; 16]>,
+    sample_rate: f32,
+
+    //Envelope: gain moves towards 1 by gain_step per sample while the gate is open, back to 0 after
+    gate: bool,
+    gain: f32,
+    gain_step: f32,
+    muted: bool,
+
+    //Xorshift state and current level of the noise waveform
+    noise: u32,
+    noise_level: f32,
+}
+
+impl Tone {
+    pub fn new(sample_rate: f32, settings: ToneSettings) -> Tone {
+        Tone {
+            phase_inc: settings.frequency / sample_rate,
+            phase: 0.0,
+            settings,
+            pattern: None,
+            sample_rate,
+            gate: false,
+            gain: 0.0,
+            gain_step: 1.0 / (ENVELOPE_TIME * sample_rate).max(1.0),
+            muted: false,
+            noise: 0x2545_F491,
+            noise_level: 1.0,
+        }
+    }
+
+    /// opens the gate while the sound timer runs, the tone fades in and out from where it is
+    pub fn set_gate(&mut self, open: bool) {
+        self.gate = open;
+    }
+
+    /// a muted tone keeps running but only writes silence
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// plays pattern in a loop from its first bit, pitch 64 is 4000 bits per second
+    pub fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        self.phase_inc = rate / self.sample_rate;
+        self.phase = 0.0;
+        self.pattern = Some(*pattern);
+    }
+
+    /// writes the next samples, between -volume and volume
+    /// the waveform only moves on while it can be heard, so a restarted tone carries on where it faded out
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            self.gain = if self.gate { (self.gain + self.gain_step).min(1.0) } else { (self.gain - self.gain_step).max(0.0) };
+            if self.gain == 0.0 {
+                *x = 0.0;
+                continue;
+            }
+            let level = match &self.pattern {
+                Some(pattern) => {
+                    let bit = self.phase as usize;
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+                },
+                None => self.level()
+            };
+            *x = if self.muted { 0.0 } else { level * self.settings.volume * self.gain };
+            let period = if self.pattern.is_some() { 128.0 } else { 1.0 };
+            self.phase += self.phase_inc;
+            if self.phase >= period {
+                self.phase %= period;
+                self.next_noise();
+            }
+        }
+    }
+
+    /// the waveform at the current phase, between -1 and 1
+    fn level(&self) -> f32 {
+        match self.settings.waveform {
+            Waveform::Square => if self.phase <= 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise_level,
+        }
+    }
+
+    fn next_noise(&mut self) {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    }
+}
+
+/// writes what the buzzer plays to a 16-bit mono wav file, one 60Hz frame at a time
+/// frames line up with the ones of a clip recorded in the same run, muting the buzzer doesn't silence it
+pub struct WavRecorder {
+    writer: hound::WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    tone: Tone,
+
+    //Pattern and pitch the tone plays, a change restarts the pattern like it does on the buzzer
+    pattern: Option<([u8; 16], u8)>,
+    frames: u64,
+}
+
+impl WavRecorder {
+    pub fn start(path: &Path, settings: ToneSettings) -> Result<WavRecorder, Chip8Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec).map_err(audio_error)?;
+        Ok(WavRecorder { writer, path: path.to_path_buf(), tone: Tone::new(SAMPLE_RATE as f32, settings), pattern: None, frames: 0 })
+    }
+
+    /// adds the sound of chip's last frame: the tone if the sound timer ran during it, silence otherwise
+    /// like the buzzer, the tone fades in and out at the edges
+    pub fn capture(&mut self, chip: &Chip8) -> Result<(), Chip8Error> {
+        if let Some(pattern) = chip.audio_pattern {
+            if self.pattern != Some((pattern, chip.pitch)) {
+                self.tone.set_pattern(&pattern, chip.pitch);
+                self.pattern = Some((pattern, chip.pitch));
+            }
+        }
+        let mut samples = [0.0; SAMPLES_PER_FRAME];
+        self.tone.set_gate(chip.sounding);
+        self.tone.fill(&mut samples);
+        for sample in samples {
+            self.writer.write_sample((sample * i16::MAX as f32) as i16).map_err(audio_error)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// writes the header sizes and closes the file, returns the number of frames
+    pub fn finish(self) -> Result<u64, Chip8Error> {
+        self.writer.finalize().map_err(audio_error)?;
+        Ok(self.frames)
+    }
+}
+
+fn audio_error(e: hound::Error) -> Chip8Error {
+    Chip8Error::Audio(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tone, ToneSettings, WavRecorder, Waveform};
+    use crate::chip8::Chip8;
+    use crate::constants::{ENVELOPE_TIME, SAMPLES_PER_FRAME, SAMPLE_RATE};
+    use crate::instruction::InstructionResult;
+    use std::fs;
+
+    #[test]
+    fn wav_follows_the_sound_timer_frame_by_frame() {
+        let path = std::env::temp_dir().join(format!("chip8-buzzer-{}.wav", std::process::id()));
+        for frames in [1, 2] {
+            // LD V0, frames; LD ST, V0; JP self
+            let mut chip = Chip8::new_default();
+            chip.load_rom(&[0x60, frames, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+            chip.PC = 0x200;
+            let mut wav = WavRecorder::start(&path, ToneSettings::default()).unwrap();
+            assert!(matches!(chip.run_frame(10).unwrap(), InstructionResult::BreakLoop));
+            wav.capture(&chip).unwrap();
+            for _ in 1..4 {
+                chip.tick_timers();
+                wav.capture(&chip).unwrap();
+            }
+            assert_eq!(wav.finish().unwrap(), 4);
+
+            let mut reader = hound::WavReader::open(&path).unwrap();
+            assert_eq!(reader.spec().sample_rate, 44100);
+            let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+            assert_eq!(samples.len(), 4 * SAMPLES_PER_FRAME);
+            //ST = n sounds for n frames: fades in from the first sample, out over the start of the frame after, then silence
+            let envelope = (ENVELOPE_TIME * SAMPLE_RATE as f32) as usize;
+            let (sound, silence) = samples.split_at(frames as usize * SAMPLES_PER_FRAME + envelope);
+            assert!(sound.iter().all(|&s| s != 0), "ST = {}", frames);
+            assert!(sound[0].abs() < sound[envelope].abs());
+            assert!(silence.iter().all(|&s| s == 0), "ST = {}", frames);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn patterns_play_bit_by_bit() {
+        //pitch 64 at 8000 samples per second: every bit lasts two samples
+        let mut tone = Tone::new(8000.0, ToneSettings { volume: 1.0, ..ToneSettings::default() });
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        tone.set_pattern(&pattern, 64);
+        tone.set_gate(true);
+        let mut out = [0.0; 8];
+        tone.fill(&mut out);
+        assert_eq!(out.map(f32::signum), [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn waveforms_stay_in_range_and_mute_silences() {
+        for name in Waveform::NAMES {
+            let waveform = Waveform::from_name(name).unwrap();
+            let mut tone = Tone::new(8000.0, ToneSettings { frequency: 1000.0, waveform, volume: 0.5 });
+            tone.set_gate(true);
+            let mut out = [0.0; 400];
+            tone.fill(&mut out);
+            assert!(out.iter().all(|s| s.abs() <= 0.5), "{}", name);
+            assert!(out.iter().any(|&s| s > 0.1) && out.iter().any(|&s| s < -0.1), "{}", name);
+
+            tone.set_muted(true);
+            tone.fill(&mut out);
+            assert!(out.iter().all(|&s| s == 0.0), "{}", name);
+        }
+    }
+}
@@ -1,17 +1,27 @@
 
-use std::{fs::File, io::{BufReader, Error, Read}, thread, time::{Duration, Instant}};
-use sdl2::{audio::AudioDevice, event::Event, keyboard::Keycode, pixels::Color, sys::KeyCode, EventPump};
-use sdl2::rect;
-use sdl2::render::WindowCanvas;
-use crate::constants::{KEYPAD_VALUES, SPRITE_PRESET};
+use std::{fs, path::Path, thread, time::Instant};
+use crate::constants::{BIG_FONT, BIG_FONT_LOCATION, DEFAULT_PITCH, FRAME_DURATION, REWIND_BUDGET, REWIND_INTERVAL, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, SPRITE_PRESET};
+use crate::audio::WavRecorder;
+use crate::error::Chip8Error;
+use crate::frontend::{DisplaySink, Frontend, InputEvent, Palette};
+use crate::keypad::Keypad;
+use crate::movie::{Movie, MovieMode};
+use crate::quirks::Quirks;
+use crate::recorder::{ClipOptions, Recorder};
+use crate::random::{RandomSource, SeededRandom};
+use crate::rewind::Rewind;
+use crate::savestate;
+use crate::screenshot;
 
 use crate::instruction;
 
 
 
+#[allow(non_snake_case)]
+#[derive(Clone)]
 pub struct Chip8 {
-    //The main memory of the machine, 4KB
-    pub memory: [u8; 4096],
+    //The main memory of the machine, 4KB or 64KB with Quirks::extended_memory
+    pub memory: Vec<u8>,
 
     //16 General purpose 8-bit registers V1 - Vf,
     //Vf isn't used by programs, it is used by some instructions as flag
@@ -20,10 +30,14 @@ pub struct Chip8 {
     //16-bit special register
     pub I: u16,
 
-    //TODO: These registers are decremented at a rate of 60Hz when non-zero
+    //These registers are decremented once per 60Hz frame when non-zero
     pub delay_timer: u8,
     pub sound_timer: u8,
 
+    //Whether the buzzer sounds for the frame that just ended: the sound timer was running before its tick,
+    //so ST = n sounds for n frames
+    pub sounding: bool,
+
     //Program Counter and Stack Pointer
     pub PC: u16,
     pub SP: u8,
@@ -31,11 +45,33 @@ pub struct Chip8 {
     //Stack, allows max 16 subroutines
     pub stack: [u16; 16],
 
+    //SUPER-CHIP RPL user flags, saved and restored by Fx75/Fx85
+    pub rpl_flags: [u8; 16],
+
     //Display
     pub display: Chip8Display,
 
-    //Canvas
-    pub canvas: WindowCanvas,
+    //Set whenever the display changes, the driver clears it after presenting
+    pub draw_flag: bool,
+
+    //State of the 16 keys, kept up to date by the driver
+    pub keypad: Keypad,
+
+    //Set while Fx0A is waiting for a key to be pressed and released
+    pub waiting_for_key: bool,
+
+    //Behaviour of the ambiguous opcodes
+    pub quirks: Quirks,
+
+    //XO-CHIP audio: 128 one-bit samples loaded by F002 and their playback pitch set by Fx3A
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+
+    //Set whenever the pattern or pitch changes, the driver clears it after passing them on
+    pub audio_flag: bool,
+
+    //Source of Cxkk, owned so a cloned machine replays the same numbers
+    pub rng: Box<dyn RandomSource>,
 }
 
 #[derive(Copy, Clone)]
@@ -45,7 +81,7 @@ pub enum Pixel {
 }
 
 impl Pixel {
-    fn is_on(&self) -> bool {
+    pub fn is_on(&self) -> bool {
         match self {
             Pixel::On => true,
             Pixel::Off => false
@@ -53,38 +89,169 @@ impl Pixel {
     }
 }
 
+/// one bitplane of the framebuffer
+pub type Plane = [[Pixel; HIRES_WIDTH]; HIRES_HEIGHT];
+
+/// framebuffer big enough for the SUPER-CHIP high resolution mode
+/// in low resolution only the top left 64x32 pixels are used
+#[derive(Clone)]
 pub struct Chip8Display {
-    pub pixels: [[Pixel; 64];32]
+    //XO-CHIP draws to two bitplanes, everything else only uses the first
+    pub planes: [Plane; 2],
+    pub hires: bool,
+
+    //Bitmask of the planes drawing, clearing and scrolling apply to, set by Fn01
+    pub selected_planes: u8
 }
 
 impl Chip8Display {
+    pub fn new() -> Chip8Display {
+        Chip8Display { planes: [[[Pixel::Off; HIRES_WIDTH]; HIRES_HEIGHT]; 2], hires: false, selected_planes: 1 }
+    }
+
+    /// the selected planes, paired with their index
+    fn selected_mut(&mut self) -> impl Iterator<Item = &mut Plane> {
+        let selected = self.selected_planes;
+        self.planes.iter_mut().enumerate().filter(move |(i, _)| selected & (1 << i) != 0).map(|(_, plane)| plane)
+    }
+
+    /// clears the selected planes
     pub fn clear(&mut self) {
-        self.pixels = [[Pixel::Off; 64]; 32];
+        for plane in self.selected_mut() {
+            *plane = [[Pixel::Off; HIRES_WIDTH]; HIRES_HEIGHT];
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    /// On if the pixel is lit in any plane
+    pub fn pixel(&self, x: usize, y: usize) -> Pixel {
+        if self.color(x, y) != 0 { Pixel::On } else { Pixel::Off }
+    }
+
+    /// the palette index of a pixel, bit 0 is the first plane and bit 1 the second
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        self.planes[0][y][x].is_on() as u8 | (self.planes[1][y][x].is_on() as u8) << 1
+    }
+
+    /// switches resolution, both planes are cleared
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[[Pixel::Off; HIRES_WIDTH]; HIRES_HEIGHT]; 2];
+    }
+
+    /// moves the selected planes down by n rows, blank rows come in at the top
+    pub fn scroll_down(&mut self, n: usize) {
+        let height = self.height();
+        for plane in self.selected_mut() {
+            for y in (0..height).rev() {
+                plane[y] = if y >= n { plane[y - n] } else { [Pixel::Off; HIRES_WIDTH] };
+            }
+        }
+    }
+
+    /// moves the selected planes up by n rows, blank rows come in at the bottom
+    pub fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        for plane in self.selected_mut() {
+            for y in 0..height {
+                plane[y] = if y + n < height { plane[y + n] } else { [Pixel::Off; HIRES_WIDTH] };
+            }
+        }
+    }
+
+    /// moves the selected planes right by n columns
+    pub fn scroll_right(&mut self, n: usize) {
+        let width = self.width();
+        for plane in self.selected_mut() {
+            for row in plane.iter_mut() {
+                for x in (0..width).rev() {
+                    row[x] = if x >= n { row[x - n] } else { Pixel::Off };
+                }
+            }
+        }
+    }
+
+    /// moves the selected planes left by n columns
+    pub fn scroll_left(&mut self, n: usize) {
+        let width = self.width();
+        for plane in self.selected_mut() {
+            for row in plane.iter_mut() {
+                for x in 0..width {
+                    row[x] = if x + n < width { row[x + n] } else { Pixel::Off };
+                }
+            }
+        }
+    }
+}
+
+impl Default for Chip8Display {
+    fn default() -> Self {
+        Chip8Display::new()
     }
 }
 
 impl Chip8 {
-    pub fn new_default(canvas: WindowCanvas) -> Chip8 {
-        let memory: [u8; 4096] = [0; 4096];
+    pub fn new_default() -> Chip8 {
+        Chip8::new(Quirks::default())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn new(quirks: Quirks) -> Chip8 {
+        let memory: Vec<u8> = vec![0; if quirks.extended_memory { 0x10000 } else { 0x1000 }];
         let Vx: [u8; 16] = [0; 16];
         let I: u16 = 0;
         let delay_timer: u8 = 0;
         let sound_timer: u8 = 0;
+        let sounding = false;
         let PC: u16 = 0;
         let SP: u8 = 0;
         let stack: [u16; 16] = [0; 16];
-        let display: Chip8Display = Chip8Display { pixels: [[Pixel::Off; 64]; 32] };
+        let rpl_flags: [u8; 16] = [0; 16];
+        let display: Chip8Display = Chip8Display::new();
+        let draw_flag = false;
+        let keypad = Keypad::new();
+        let waiting_for_key = false;
+        let audio_pattern = None;
+        let pitch = DEFAULT_PITCH;
+        let audio_flag = false;
+        let rng: Box<dyn RandomSource> = Box::new(SeededRandom::from_entropy());
         let mut chip = Chip8 {
-            memory, Vx, I, delay_timer, sound_timer, PC, SP, stack, display, canvas
+            memory, Vx, I, delay_timer, sound_timer, sounding, PC, SP, stack, rpl_flags, display, draw_flag, keypad, waiting_for_key, quirks,
+            audio_pattern, pitch, audio_flag, rng
         };
         let mut location = 0x50;
         for sprite in SPRITE_PRESET {
             chip.load_sprite(sprite, location);
             location += 5;
         }
+        chip.memory[BIG_FONT_LOCATION..BIG_FONT_LOCATION + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
         chip
     }
 
+    /// restarts the random number generator from seed, so Cxkk repeats from run to run
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Box::new(SeededRandom::new(seed));
+    }
+
+    /// replaces the random number generator, e.g. with a random::ScriptedRandom in tests
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    /// becomes chip, e.g. a loaded save state, but keeps drawing from this machine's random number generator
+    /// so a seeded run stays reproducible across loads
+    pub fn restore(&mut self, mut chip: Chip8) {
+        std::mem::swap(&mut chip.rng, &mut self.rng);
+        *self = chip;
+    }
+
     fn load_sprite(&mut self, sprite: Sprite,mut location: usize) {
         let mut i: usize = 0;
         while i < sprite.size {
@@ -95,49 +262,83 @@ impl Chip8 {
     }
 
 
-    pub fn draw_sprite_in_mem_to_x_y(&mut self, sprite_loc: usize, mut x: usize, mut y: usize, n: usize) {
-        let mut current_byte = self.memory[sprite_loc];
-        let starting_x = x;
-        for i in 0..n {
-            current_byte = self.memory[sprite_loc + i];
-            x = starting_x;
-            let mut mask = 0b10000000;
-            while mask != 0 {
-                if current_byte & mask == mask {
-                    self.display.pixels[y % 32][x % 64] = match self.display.pixels[y % 32][x % 64] {
+    /// start of the len bytes at address, or a fault if they don't fit in memory
+    pub fn mem_range(&self, address: usize, len: usize) -> Result<usize, Chip8Error> {
+        if address + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { address: address.max(self.memory.len()) });
+        }
+        Ok(address)
+    }
+
+    /// xors the sprite at sprite_loc onto the selected planes at (x, y), VF is set to 1 if a lit pixel was turned off
+    /// n rows of 8 pixels, or a 16x16 sprite when n is 0 and the quirks have big sprites, nothing otherwise
+    /// with both planes selected the second plane's sprite follows the first one in memory
+    /// the origin always wraps around the screen, the rest of the sprite clips or wraps depending on Quirks::clip_sprites
+    pub fn draw_sprite_in_mem_to_x_y(&mut self, sprite_loc: usize, x: usize, y: usize, n: usize) -> Result<(), Chip8Error> {
+        let (rows, bytes_per_row) = if n == 0 && self.quirks.big_sprites { (16, 2) } else { (n, 1) };
+        let sprite_size = rows * bytes_per_row;
+        let plane_count = self.display.selected_planes.count_ones() as usize;
+        let mut sprite = self.mem_range(sprite_loc, sprite_size * plane_count)?;
+        let width = self.display.width();
+        let height = self.display.height();
+        let (x, y) = (x % width, y % height);
+        let mut collision = false;
+        for plane in 0..2 {
+            if self.display.selected_planes & (1 << plane) == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                let mut py = y + row;
+                if py >= height {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    py %= height;
+                }
+                for column in 0..8 * bytes_per_row {
+                    let current_byte = self.memory[sprite + row * bytes_per_row + column / 8];
+                    if current_byte & (0x80 >> (column % 8)) == 0 {
+                        continue;
+                    }
+                    let mut px = x + column;
+                    if px >= width {
+                        if self.quirks.clip_sprites {
+                            break;
+                        }
+                        px %= width;
+                    }
+                    let pixel = &mut self.display.planes[plane][py][px];
+                    *pixel = match *pixel {
                         Pixel::Off => Pixel::On,
-                        Pixel::On => Pixel::Off
-                    } 
+                        Pixel::On => {
+                            collision = true;
+                            Pixel::Off
+                        }
+                    }
                 }
-                mask = mask >> 1;
-                x += 1;
             }
-            y += 1;
+            sprite += sprite_size;
         }
 
-        self.draw_display_to_window();
+        self.Vx[0xF] = collision as u8;
+        self.draw_flag = true;
+        Ok(())
     }
 
 
     /// loads .ch8 file under roms to the memory of the emulator
-    pub fn load_file_to_mem(&mut self, filename: &str) -> Result<(), Error> {
-        let filename = String::from("./roms/") + filename;
-        let f = match File::open(filename) {
-            Ok(f) => {f},
-            Err(e) => {return Err(e)}
-        };
-        let reader = BufReader::new(f);
-        let reader_bytes = reader.bytes();
-        let mut location: usize = 0x200;
-        for byte in reader_bytes {
-            match byte {
-                Ok(b) => {
-                    self.memory[location] = b;
-                    location += 1;
-                },
-                Err(e) => println!("Error reading file: {}", e)
-            }
+    pub fn load_file_to_mem(&mut self, filename: &str) -> Result<(), Chip8Error> {
+        let rom = read_rom(filename)?;
+        self.load_rom(&rom)
+    }
+
+    /// copies a rom image to 0x200
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.memory.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
         }
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
         Ok(())
     }
 
@@ -148,7 +349,7 @@ impl Chip8 {
             for j in (512 * i)..(512 * i + 511) {
                 print!("{} ", &self.memory[j]);
             }
-            print!("\n");
+            println!();
         }
         println!("-------------------------------------------");
     }
@@ -167,229 +368,414 @@ impl Chip8 {
         for i in 0..16 {
             print!("{} ", &self.stack[i]);
         }
-        print!("\n");
+        println!();
     }
 
-    pub fn start_device(&mut self, filename: &str, is_debug: bool, event_pump: EventPump, audio_device: AudioDevice<crate::SquareWave>) -> Result<(), Error> {
-        match self.load_file_to_mem(&filename) {
-            Err(e) => return Err(e),
-            _ => {}
-        };
+    pub fn start_device(&mut self, filename: &str, is_debug: bool, instructions_per_frame: u32, movie: MovieMode, clip: ClipOptions, frontend: &mut Frontend) -> Result<(), Chip8Error> {
+        self.load_file_to_mem(filename)?;
         self.PC = 0x200;
 
         if is_debug {
-            self.start_debug(event_pump);
+            if !matches!(movie, MovieMode::Off) {
+                return Err(Chip8Error::InvalidArgument(String::from("Movies can't be recorded or played in debug mode!")));
+            }
+            if clip.path.is_some() || clip.audio.is_some() {
+                return Err(Chip8Error::InvalidArgument(String::from("Clips and audio can't be recorded in debug mode!")));
+            }
+            self.start_debug(filename, clip.screenshot_scale, frontend)
         }else {
-            self.start_loop(event_pump, audio_device);
+            self.start_loop(filename, instructions_per_frame, movie, clip, frontend)
         }
-        Ok(())
     }
 
-    fn start_loop(&mut self, mut event_pump: EventPump, audio_device: AudioDevice<crate::SquareWave>) {
-        let mut dt_last_dec = Instant::now();
-        let mut st_last_dec = Instant::now();
-        let mut wait_for_key_flag = false;
-        let mut store_reg = 0;
-        'running: loop {
-            let instruction: u16 = ((self.memory[self.PC as usize] as u16) << 8) | (self.memory[(self.PC + 1) as usize]) as u16;
-            
-            self.PC += 2;
-
-            match self.decode_execute_instruction(instruction) {
-                instruction::InstructionResult::BreakLoop => {
-                    if (self.sound_timer == 0) {
-                        break 'running
-                    } else {
-                        'decay: loop {
-                            let elapsed_time = st_last_dec.elapsed().as_nanos();
-                            let to_dec = elapsed_time * 3 / 50_000_000;
-                            if self.sound_timer > to_dec as u8 {
-                                if to_dec != 0 {
-                                    self.sound_timer -= to_dec as u8;
-                                    st_last_dec = Instant::now();
-                                }
-                            } else {
-                                self.sound_timer = 0;
-                                audio_device.pause();
-                                break 'decay;
-                            }
-                            thread::sleep(Duration::new(0, 1_000_000));
-                        }
+    /// writes or restores the save state slot for rom, failures are printed and the game goes on
+    /// returns true if the machine was replaced
+    fn handle_state_event(&mut self, rom: &str, event: InputEvent) -> bool {
+        match event {
+            InputEvent::SaveState(slot) => {
+                let path = savestate::slot_path(rom, slot);
+                match savestate::save_state_to_file(self, &path) {
+                    Ok(()) => println!("Saved state to {}", path.display()),
+                    Err(e) => println!("Could not save state to {}: {}", path.display(), e)
+                }
+                false
+            },
+            InputEvent::LoadState(slot) => {
+                let path = savestate::slot_path(rom, slot);
+                match savestate::load_state_from_file(&path) {
+                    Ok(chip) => {
+                        self.restore(chip);
+                        println!("Loaded state from {}", path.display());
+                        true
+                    },
+                    Err(e) => {
+                        println!("Could not load state from {}: {}", path.display(), e);
+                        false
                     }
-                    break 'running;
-                },
-                instruction::InstructionResult::StartDelayTimer => { 
-                    dt_last_dec = Instant::now();
-                },
-                instruction::InstructionResult::StartSoundTimer => {
-                    st_last_dec = Instant::now();
-                    audio_device.resume();
+                }
+            },
+            _ => false
+        }
+    }
+
+    /// writes the display at scale to the next free screenshot file for rom, failures are printed and the game goes on
+    fn take_screenshot(&self, rom: &str, scale: u32, palette: &Palette) {
+        let path = screenshot::screenshot_path(rom);
+        match screenshot::save_png(&self.display, scale, palette, &path) {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(e) => println!("Could not save screenshot to {}: {}", path.display(), e)
+        }
+    }
+
+    /// reads the instruction word at PC
+    pub fn fetch(&self) -> Result<u16, Chip8Error> {
+        let pc = self.mem_range(self.PC as usize, 2)?;
+        Ok(((self.memory[pc] as u16) << 8) | self.memory[pc + 1] as u16)
+    }
+
+    /// fetches the instruction at PC and executes it
+    /// on a fault PC is left pointing at the faulting instruction
+    pub fn step(&mut self) -> Result<instruction::InstructionResult, Chip8Error> {
+        let opcode = self.fetch()?;
+        let address = self.PC;
+        self.PC = self.PC.wrapping_add(2);
+        let result = if opcode == 0xF000 && self.quirks.extended_memory {
+            //F000 nnnn carries its operand in the next word, only XO-CHIP has it
+            match self.fetch() {
+                Ok(nnnn) => {
+                    self.PC = self.PC.wrapping_add(2);
+                    self.execute(instruction::Instruction::LongLoadI { nnnn })
                 },
-                instruction::InstructionResult::SkipIfPressed(key) => {
-                    let qkeycode = KEYPAD_VALUES[key as usize];
-                    'search: for event in event_pump.poll_iter() {
-                        match event {
-                            Event::KeyDown { keycode: qkeycode, .. } => {
-                                self.PC += 2;
-                                break 'search;
-                            },
-                            _ => {}
-                        }
-                    }
+                Err(e) => Err(e)
+            }
+        } else {
+            self.decode_execute_instruction(opcode)
+        };
+        if result.is_err() {
+            self.PC = address;
+        }
+        result
+    }
+
+    /// decrements both timers by one, called once per 60Hz frame
+    pub fn tick_timers(&mut self) {
+        self.sounding = self.sound_timer > 0;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// runs one 60Hz frame: up to instructions_per_frame instructions, then a timer tick
+    /// returns BreakLoop if the program halted during the frame
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<instruction::InstructionResult, Chip8Error> {
+        let mut result = instruction::InstructionResult::Ok;
+        for _ in 0..instructions_per_frame {
+            match self.step()? {
+                instruction::InstructionResult::BreakLoop => {
+                    result = instruction::InstructionResult::BreakLoop;
+                    break;
                 },
-                instruction::InstructionResult::SkipIfNotPressed(key) => {
-                    let mut flag = false;
-                    let qkeycode = KEYPAD_VALUES[key as usize];
-                    'search: for event in event_pump.poll_iter() {
-                        match event {
-                            Event::KeyDown { keycode: Some(qkeycode), .. } => {
-                                flag = true;
-                                break 'search;
-                            },
-                            _ => {}
+                instruction::InstructionResult::DisplayWait => break,
+                instruction::InstructionResult::Ok => {}
+            }
+        }
+        self.tick_timers();
+        Ok(result)
+    }
+
+    /// hands the display to the frontend if the last instruction touched it
+    fn present_if_needed(&mut self, display: &mut dyn DisplaySink) -> Result<(), Chip8Error> {
+        if self.draw_flag {
+            display.present(&self.display)?;
+            self.draw_flag = false;
+        }
+        Ok(())
+    }
+
+    /// runs the rom in 60Hz frames until it halts, faults or the frontend quits
+    /// a recorded movie or clip is written even when the run ends in a fault
+    fn start_loop(&mut self, rom: &str, instructions_per_frame: u32, movie: MovieMode, clip: ClipOptions, frontend: &mut Frontend) -> Result<(), Chip8Error> {
+        let mut next_frame = Instant::now();
+        let mut buzzing = false;
+        let mut halted = false;
+        let mut rewind = Rewind::new(REWIND_BUDGET, REWIND_INTERVAL, instructions_per_frame);
+        let mut rewinding = false;
+
+        //frames are counted instead of timed, so a movie replays the same no matter how fast the host is
+        let mut frame: u64 = 0;
+        let (mut recording, mut playing) = match movie {
+            MovieMode::Off => (None, None),
+            MovieMode::Record { path, seed, rom_hash } => {
+                self.seed(seed);
+                (Some((path, Movie::new(rom_hash, self.quirks, seed, instructions_per_frame))), None)
+            },
+            MovieMode::Play(movie) => {
+                self.seed(movie.seed);
+                (None, Some(movie))
+            }
+        };
+
+        //every frame shown is captured, also while rewinding or halted
+        let mut recorder = match &clip.path {
+            Some(path) => Some(Recorder::start(path, clip.scale, &frontend.display.palette())?),
+            None => None
+        };
+        let mut wav = match &clip.audio {
+            Some(path) => Some(WavRecorder::start(path, clip.tone)?),
+            None => None
+        };
+
+        let mut result = Ok(());
+        'running: loop {
+            let mut events = frontend.keypad.poll_events();
+            if let Some(movie) = &playing {
+                //live keys are ignored until the movie is over
+                events.retain(|e| !matches!(e, InputEvent::KeyDown(_) | InputEvent::KeyUp(_)));
+                if frame < movie.frames {
+                    events.extend(movie.events_at(frame));
+                } else {
+                    println!("Movie finished after {} frames", frame);
+                    playing = None;
+                }
+            }
+            //going back in time would make the movie disagree with the run
+            let movie_active = recording.is_some() || playing.is_some();
+
+            for &event in &events {
+                match event {
+                    InputEvent::Quit => break 'running,
+                    InputEvent::LoadState(_) | InputEvent::Rewind(true) if movie_active => {
+                        println!("Save states and rewind are not available while a movie is recorded or played");
+                    },
+                    InputEvent::SaveState(_) | InputEvent::LoadState(_) => {
+                        let replaced = self.handle_state_event(rom, event);
+                        if replaced {
+                            halted = false;
+                            rewind.clear();
                         }
-                    }
-                    if !flag {
-                        self.PC += 2;
-                    }
-                },
-                instruction::InstructionResult::WaitForKey(reg) => {
-                    self.PC -= 2;
-                    wait_for_key_flag = true;
-                    store_reg = reg;
-                },
-                instruction::InstructionResult::Ok => {
-                    for event in event_pump.poll_iter() {
-                        match event {
-                            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                                break 'running;
-                            },
-                            _ => {}
+                    },
+                    InputEvent::Rewind(held) => rewinding = held && !movie_active,
+                    InputEvent::Screenshot => self.take_screenshot(rom, clip.screenshot_scale, &frontend.display.palette()),
+                    InputEvent::ToggleMute => frontend.buzzer.toggle_mute(),
+                    InputEvent::CyclePalette => {
+                        frontend.display.cycle_palette();
+                        self.draw_flag = true;
+                    },
+                    InputEvent::ToggleFullscreen => {
+                        frontend.display.toggle_fullscreen();
+                        self.draw_flag = true;
+                    },
+                    InputEvent::Redraw => self.draw_flag = true,
+                    InputEvent::ToggleClip => match recorder.take() {
+                        Some(finished) => finish_clip(finished),
+                        None => {
+                            let path = screenshot::next_free_path(rom, "gif");
+                            match Recorder::start(&path, clip.scale, &frontend.display.palette()) {
+                                Ok(started) => {
+                                    println!("Recording clip to {}", path.display());
+                                    recorder = Some(started);
+                                },
+                                Err(e) => println!("Could not record clip to {}: {}", path.display(), e)
+                            }
                         }
-                    }
+                    },
+                    _ => {}
                 }
-            };
-            
-
-            
+            }
 
-            thread::sleep(Duration::new(0, 100_000));
+            if rewinding {
+                //show the frame before, the keys held right now carry on once rewinding stops
+                if let Some(chip) = rewind.step_back() {
+                    let keypad = self.keypad;
+                    *self = chip;
+                    self.keypad = keypad;
+                    //the buzzer has to pick up the pattern and pitch of the frame shown
+                    self.draw_flag = true;
+                    self.audio_flag = true;
+                    halted = false;
+                }
+                for &event in &events {
+                    self.keypad.handle_event(event);
+                }
+            } else {
+                rewind.record(self, &events);
+                for &event in &events {
+                    if let Some((_, movie)) = &mut recording {
+                        movie.record(frame, event);
+                    }
+                    self.keypad.handle_event(event);
+                }
 
-            if wait_for_key_flag {
-                for event in event_pump.poll_iter() {
-                    match event {
-                        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                            wait_for_key_flag = false;
+                if halted {
+                    //let the sound timer run out before leaving
+                    self.tick_timers();
+                } else {
+                    match self.run_frame(instructions_per_frame) {
+                        Ok(instruction::InstructionResult::BreakLoop) => halted = true,
+                        Ok(_) => {},
+                        Err(e) => {
+                            result = Err(e);
+                            frame += 1;
                             break 'running;
-                        },
-                        Event::KeyDown {keycode: Some(k), ..} => {
-                            
-                            self.Vx[store_reg as usize] = KEYPAD_VALUES.iter().position(|&key| key == k ).unwrap() as u8;
-                            wait_for_key_flag = false;
-                            self.PC += 2;
-                        },
-                        _ => {}
+                        }
                     }
                 }
+                frame += 1;
             }
 
-            if self.sound_timer != 0 {
-                let elapsed_time = st_last_dec.elapsed().as_nanos();
-                let to_dec = elapsed_time * 3 / 50_000_000;
-                if self.sound_timer > to_dec as u8 {
-                    if to_dec != 0 {
-                        self.sound_timer -= to_dec as u8;
-                        st_last_dec = Instant::now();
-                    }
-                } else {
-                    self.sound_timer = 0;
-                    audio_device.pause();
+            if self.audio_flag {
+                if let Some(pattern) = &self.audio_pattern {
+                    frontend.buzzer.set_pattern(pattern, self.pitch);
                 }
+                self.audio_flag = false;
             }
-            if self.delay_timer != 0 {
-                let elapsed_time = dt_last_dec.elapsed().as_nanos();
-                let to_dec = elapsed_time * 3 / 50_000_000;
-                if self.delay_timer > to_dec as u8 {
-                    if to_dec != 0 {
-                        self.delay_timer -= to_dec as u8;
-                        dt_last_dec = Instant::now();
-                    }
-                } else {
-                    self.delay_timer = 0;
+
+            if self.sounding && !buzzing {
+                frontend.buzzer.start();
+                buzzing = true;
+            } else if !self.sounding && buzzing {
+                frontend.buzzer.stop();
+                buzzing = false;
+            }
+
+            if let Err(e) = self.present_if_needed(frontend.display) {
+                result = Err(e);
+                break 'running;
+            }
+            if let Some(clip) = &mut recorder {
+                if let Err(e) = clip.capture(&self.display) {
+                    println!("Stopped recording clip to {}: {}", clip.path().display(), e);
+                    recorder = None;
                 }
             }
-        }
-        println!("Execution finished, press space to leave");
-        'exit: loop {
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                        break 'exit;
-                    },
-                    _ => {}
+            if let Some(audio) = &mut wav {
+                if let Err(e) = audio.capture(self) {
+                    println!("Stopped recording audio to {}: {}", audio.path().display(), e);
+                    wav = None;
                 }
             }
+
+            if halted && !self.sounding {
+                break 'running;
+            }
+
+            next_frame += FRAME_DURATION;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                //running behind, don't try to catch up
+                next_frame = now;
+            }
+        }
+        if buzzing {
+            frontend.buzzer.stop();
+        }
+        if let Some(clip) = recorder {
+            finish_clip(clip);
+        }
+        if let Some(audio) = wav {
+            let path = audio.path().to_path_buf();
+            match audio.finish() {
+                Ok(frames) => println!("Recorded {} frames of audio to {}", frames, path.display()),
+                Err(e) => println!("Could not finish audio {}: {}", path.display(), e)
+            }
+        }
+        if let Some((path, mut movie)) = recording {
+            movie.frames = frame;
+            movie.save(&path)?;
+            println!("Recorded {} frames to {}", frame, path.display());
         }
+        result
     }
 
-    fn start_debug(&mut self, mut event_pump: EventPump) {
+    /// steps through the rom one instruction at a time
+    /// faults are printed and leave PC on the faulting instruction so it can be inspected
+    fn start_debug(&mut self, rom: &str, screenshot_scale: u32, frontend: &mut Frontend) -> Result<(), Chip8Error> {
         'running: loop {
             
-            let cur_instruction: u16 = ((self.memory[self.PC as usize] as u16) << 8) | (self.memory[(self.PC + 1) as usize]) as u16;
-            for event in event_pump.poll_iter() {
+            let cur_instruction = self.fetch()?;
+            for event in frontend.keypad.poll_events() {
                 match event {
-                    Event::Quit {..} |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    InputEvent::Quit => {
                         break 'running
                     },
-                    Event::KeyDown { keycode: Some(Keycode::Right), ..} => {
-                        self.PC += 2;
-                        match self.decode_execute_instruction(cur_instruction) {
-                            instruction::InstructionResult::BreakLoop => break 'running,
-                            instruction::InstructionResult::StartDelayTimer => {},
-                            instruction::InstructionResult::StartSoundTimer => {},
-                            instruction::InstructionResult::SkipIfPressed(key) => {todo!()},
-                            instruction::InstructionResult::SkipIfNotPressed(key) => {todo!()},
-                            instruction::InstructionResult::WaitForKey(reg) => {todo!()},
-                            instruction::InstructionResult::Ok => {}
+                    InputEvent::Step => {
+                        let address = self.PC;
+                        let result = self.step();
+                        self.present_if_needed(frontend.display)?;
+                        match result {
+                            Ok(instruction::InstructionResult::BreakLoop) => break 'running,
+                            Ok(_) => {
+                                println!("Executed instruction: {:#04x}, at mem loc: {:#04x}", cur_instruction, address);
+                            },
+                            Err(e) => println!("Fault: {}", e)
                         };
-                        println!("Executed instruction: {:#04x}, at mem loc: {:#04x}", cur_instruction, self.PC - 2);
+                        //PC moved, the rest of the events belong to the next instruction
+                        continue 'running;
                     },
-                    Event::KeyDown { keycode: Some(Keycode::M), .. } => {
+                    InputEvent::DumpMemory => {
                         self.get_mem_state();
                     },
-                    Event::KeyDown { keycode: Some(Keycode::Y), .. } => {
+                    InputEvent::DumpRegisters => {
                         self.get_reg_state();
                     },
-                    Event::KeyDown { keycode: Some(Keycode::K), .. } => {
+                    InputEvent::ShowInstruction => {
                         println!("Current instruction: {:#04x}, at mem loc: {:#04x}", cur_instruction, self.PC);
                     },
-                    _ => {}
+                    InputEvent::KeyDown(_) | InputEvent::KeyUp(_) => {
+                        self.keypad.handle_event(event);
+                    },
+                    InputEvent::SaveState(_) | InputEvent::LoadState(_) => {
+                        if self.handle_state_event(rom, event) {
+                            self.present_if_needed(frontend.display)?;
+                            continue 'running;
+                        }
+                    },
+                    InputEvent::Screenshot => {
+                        self.take_screenshot(rom, screenshot_scale, &frontend.display.palette());
+                    },
+                    InputEvent::ToggleMute => {
+                        frontend.buzzer.toggle_mute();
+                    },
+                    InputEvent::CyclePalette => {
+                        frontend.display.cycle_palette();
+                        self.draw_flag = true;
+                        self.present_if_needed(frontend.display)?;
+                    },
+                    InputEvent::ToggleFullscreen => {
+                        frontend.display.toggle_fullscreen();
+                        self.draw_flag = true;
+                        self.present_if_needed(frontend.display)?;
+                    },
+                    InputEvent::Redraw => {
+                        self.draw_flag = true;
+                        self.present_if_needed(frontend.display)?;
+                    },
+                    //stepping backwards and clips are only available while running
+                    InputEvent::Rewind(_) | InputEvent::ToggleClip => {}
                 }
             }
         }
+        Ok(())
     }
+}
 
-    /// draws the pixels of Chip8isplay to SDL2 canvas
-    /// pixels are represented as 10x10 rectangles
-    /// called by the running loop of canvas window
-    pub fn draw_display_to_window(&mut self) {
-        let mut y = 0;
-        self.canvas.set_draw_color(Color::RGB(100, 225, 0));
-        for row in self.display.pixels {
-            let mut x = 0;
-            for pixel in row {
-                if pixel.is_on() {
-                    let rect = rect::Rect::new(x, y, 10, 10);
-                    self.canvas.draw_rect(rect).unwrap();
-                    self.canvas.fill_rect(rect).unwrap();
-                } 
-                x += 10;
-            }
-            y += 10;
-        }
-        self.canvas.present();
+/// closes a clip the run loop was recording, failures are printed
+fn finish_clip(clip: Recorder) {
+    let path = clip.path().to_path_buf();
+    match clip.finish() {
+        Ok(frames) => println!("Recorded {} frames to {}", frames, path.display()),
+        Err(e) => println!("Could not finish clip {}: {}", path.display(), e)
+    }
+}
+
+/// reads a .ch8 file from the roms directory, falling back to the path as given
+pub fn read_rom(filename: &str) -> Result<Vec<u8>, Chip8Error> {
+    let in_roms = Path::new("./roms").join(filename);
+    if in_roms.is_file() {
+        return Ok(fs::read(in_roms)?);
     }
+    Ok(fs::read(filename)?)
 }
 
 pub struct Sprite {
@@ -1,7 +1,7 @@
 
 
     use crate::chip8::Sprite;
-    use sdl2::keyboard::Keycode;
+    use std::time::Duration;
     
 const ZERO_SPRITE: Sprite = Sprite {bytes: [0xF0, 0x90, 0x90, 0x90, 0xF0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], size: 5};
 const ONE_SPRITE: Sprite = Sprite {bytes: [0x20, 0x60, 0x20, 0x20, 0x70, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], size: 5};
@@ -21,24 +21,76 @@ const E_SPRITE: Sprite = Sprite {bytes: [0xF0, 0x80, 0xF0, 0x80, 0xF0, 0, 0, 0,
 const F_SPRITE: Sprite = Sprite {bytes: [0xF0, 0x80, 0xF0, 0x80, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], size: 5};
 
 
+/// SUPER-CHIP 8x10 hex font used by Fx30
+pub const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// the big font is stored right after the small one
+pub const BIG_FONT_LOCATION: usize = 0xA0;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
 pub const SPRITE_PRESET: [Sprite; 16] = [ZERO_SPRITE, ONE_SPRITE, TWO_SPRITE, THREE_SPRITE, FOUR_SPRITE, FIVE_SPRITE, SIX_SPRITE, SEVEN_SPRITE,
                                     EIGHT_SPRITE, NINE_SPRITE, A_SPRITE, B_SPRITE, C_SPRITE, D_SPRITE, E_SPRITE, F_SPRITE];
 
-pub const KEYPAD_VALUES: [Keycode; 16] = [
-    Keycode::X,
-    Keycode::Num1,
-    Keycode::Num2,
-    Keycode::Num3,
-    Keycode::Q,
-    Keycode::W,
-    Keycode::E,
-    Keycode::A,
-    Keycode::S,
-    Keycode::D,
-    Keycode::Z,
-    Keycode::C,
-    Keycode::Num4,
-    Keycode::R,
-    Keycode::F,
-    Keycode::V
-];
+/// length of one 60Hz frame, timers tick and the display is presented once per frame
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// how many instructions run in a frame unless configured otherwise
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+/// bytes of snapshots kept for rewinding, about two minutes of a 4KB game
+pub const REWIND_BUDGET: usize = 16 * 1024 * 1024;
+
+/// frames between two rewind snapshots, the frames in between are re-simulated
+pub const REWIND_INTERVAL: usize = 10;
+
+/// XO-CHIP pitch that plays audio patterns at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// colours of the four pixel values: black background, green and orange planes, white where both are lit
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [100, 225, 0], [225, 100, 0], [255, 255, 255]];
+
+/// screenshots and headless pngs are this many times the size of the chip8 display unless --screenshot-scale is given
+pub const DEFAULT_SCREENSHOT_SCALE: u32 = 10;
+
+/// clips are recorded at this many times 128 x 64 unless configured otherwise
+pub const DEFAULT_CLIP_SCALE: u32 = 2;
+
+/// samples per second of the buzzer, 735 of them make up one 60Hz frame
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// the buzzer plays a tone of this frequency unless configured otherwise, until an XO-CHIP pattern is loaded
+pub const TONE_FREQUENCY: f32 = 440.0;
+
+/// amplitude of the buzzer, 1.0 is full scale
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+/// seconds the buzzer takes to fade in and out, long enough not to click
+pub const ENVELOPE_TIME: f32 = 0.005;
+
+/// read from the working directory when no --config is given
+pub const CONFIG_FILE: &str = "chip8.cfg";
+
+/// the window starts out this many times the size of the 64 x 32 display unless configured otherwise
+pub const DEFAULT_SCALE: u32 = 10;
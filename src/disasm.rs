@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::error::Chip8Error;
+use crate::instruction::{decode_long, Instruction};
+
+/// what a line of the listing holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    Data(Vec<u8>),
+}
+
+/// one line of a disassembly, optionally preceded by a label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub label: Option<String>,
+    pub item: Item,
+}
+
+/// how many bytes go on a single db line
+const DATA_PER_LINE: usize = 8;
+
+/// addresses are 16 bits, nothing can be loaded past this
+const ADDRESS_SPACE: usize = 0x10000;
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", mnemonic(self, &|nnn| format!("{:#05x}", nnn)))
+    }
+}
+
+/// formats an instruction, addresses go through the label function
+fn mnemonic(instruction: &Instruction, label: &dyn Fn(u16) -> String) -> String {
+    match *instruction {
+        Instruction::Clear => String::from("CLS"),
+        Instruction::Return => String::from("RET"),
+        Instruction::ScrollDown { n } => format!("SCD {}", n),
+        Instruction::ScrollUp { n } => format!("SCU {}", n),
+        Instruction::ScrollRight => String::from("SCR"),
+        Instruction::ScrollLeft => String::from("SCL"),
+        Instruction::Exit => String::from("EXIT"),
+        Instruction::LowRes => String::from("LOW"),
+        Instruction::HighRes => String::from("HIGH"),
+        Instruction::Jump { nnn } => format!("JP {}", label(nnn)),
+        Instruction::Call { nnn } => format!("CALL {}", label(nnn)),
+        Instruction::SkipEqImm { x, kk } => format!("SE V{:X}, {:#04x}", x, kk),
+        Instruction::SkipNeImm { x, kk } => format!("SNE V{:X}, {:#04x}", x, kk),
+        Instruction::SkipEqReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SaveRange { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+        Instruction::LoadRange { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+        Instruction::LoadImm { x, kk } => format!("LD V{:X}, {:#04x}", x, kk),
+        Instruction::AddImm { x, kk } => format!("ADD V{:X}, {:#04x}", x, kk),
+        Instruction::Load { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubN { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipNeReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LoadI { nnn } => format!("LD I, {}", label(nnn)),
+        Instruction::JumpV0 { nnn } => format!("JP V0, {}", label(nnn)),
+        Instruction::Random { x, kk } => format!("RND V{:X}, {:#04x}", x, kk),
+        Instruction::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipKeyPressed { x } => format!("SKP V{:X}", x),
+        Instruction::SkipKeyNotPressed { x } => format!("SKNP V{:X}", x),
+        Instruction::LongLoadI { nnnn } => format!("LD I, long {}", label(nnnn)),
+        Instruction::SelectPlanes { n } => format!("PLANE {}", n),
+        Instruction::LoadAudio => String::from("AUDIO"),
+        Instruction::LoadDelay { x } => format!("LD V{:X}, DT", x),
+        Instruction::WaitKey { x } => format!("LD V{:X}, K", x),
+        Instruction::SetDelay { x } => format!("LD DT, V{:X}", x),
+        Instruction::SetSound { x } => format!("LD ST, V{:X}", x),
+        Instruction::AddI { x } => format!("ADD I, V{:X}", x),
+        Instruction::LoadFont { x } => format!("LD F, V{:X}", x),
+        Instruction::LoadBigFont { x } => format!("LD HF, V{:X}", x),
+        Instruction::StoreBcd { x } => format!("LD B, V{:X}", x),
+        Instruction::SetPitch { x } => format!("PITCH V{:X}", x),
+        Instruction::StoreRegs { x } => format!("LD [I], V{:X}", x),
+        Instruction::LoadRegs { x } => format!("LD V{:X}, [I]", x),
+        Instruction::StoreFlags { x } => format!("LD R, V{:X}", x),
+        Instruction::LoadFlags { x } => format!("LD V{:X}, R", x),
+    }
+}
+
+/// addresses execution can continue at after the instruction at address
+/// skips jump over 4 bytes when the following instruction is F000 nnnn
+fn successors(instruction: &Instruction, address: u16, next_is_long: bool) -> Vec<u16> {
+    let next = address.wrapping_add(instruction.size());
+    let skipped = next.wrapping_add(if next_is_long { 4 } else { 2 });
+    match *instruction {
+        Instruction::Return | Instruction::Exit => vec![],
+        Instruction::Jump { nnn } => vec![nnn],
+        Instruction::JumpV0 { nnn } => vec![nnn],
+        Instruction::Call { nnn } => vec![nnn, next],
+        Instruction::SkipEqImm { .. } |
+        Instruction::SkipNeImm { .. } |
+        Instruction::SkipEqReg { .. } |
+        Instruction::SkipNeReg { .. } |
+        Instruction::SkipKeyPressed { .. } |
+        Instruction::SkipKeyNotPressed { .. } => vec![next, skipped],
+        _ => vec![next],
+    }
+}
+
+/// disassembles bytes loaded at origin, execution is assumed to start at origin
+/// only instructions reachable from there are treated as code, the rest is data
+/// bytes that would run past the end of the 16-bit address space are rejected
+pub fn disassemble(bytes: &[u8], origin: u16) -> Result<Vec<Line>, Chip8Error> {
+    let max = ADDRESS_SPACE - origin as usize;
+    if bytes.len() > max {
+        return Err(Chip8Error::RomTooLarge { size: bytes.len(), max });
+    }
+    let end = origin as usize + bytes.len();
+    let in_range = |address: u16| (address as usize) >= origin as usize && (address as usize) + 2 <= end;
+    let word = |address: u16| {
+        let i = (address - origin) as usize;
+        ((bytes[i] as u16) << 8) | bytes[i + 1] as u16
+    };
+
+    //walk every path from the entry point
+    let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut targets: BTreeMap<u16, &str> = BTreeMap::new();
+    let mut pending = vec![origin];
+    while let Some(address) = pending.pop() {
+        if !in_range(address) || code.contains_key(&address) {
+            continue;
+        }
+        let next = address.wrapping_add(2);
+        let next_word = if in_range(next) { word(next) } else { 0 };
+        let instruction = match decode_long(word(address), next_word) {
+            Ok(i) => i,
+            Err(_) => continue
+        };
+        if instruction.size() == 4 && !in_range(next) {
+            continue;
+        }
+        let next_is_long = in_range(next.wrapping_add(instruction.size() - 2)) && word(next.wrapping_add(instruction.size() - 2)) == 0xF000;
+        code.insert(address, instruction);
+        match instruction {
+            Instruction::Call { nnn } => { targets.insert(nnn, "sub"); },
+            Instruction::Jump { nnn } | Instruction::JumpV0 { nnn } => { targets.entry(nnn).or_insert("loc"); },
+            Instruction::LoadI { nnn } | Instruction::LongLoadI { nnnn: nnn } => { targets.entry(nnn).or_insert("data"); },
+            _ => {}
+        }
+        pending.extend(successors(&instruction, address, next_is_long));
+    }
+
+    //instructions may overlap when a rom jumps into the middle of one, keep the first
+    let mut code_bytes = BTreeSet::new();
+    code.retain(|&address, instruction| {
+        let bytes = address as usize..address as usize + instruction.size() as usize;
+        if bytes.clone().any(|b| code_bytes.contains(&b)) {
+            return false;
+        }
+        code_bytes.extend(bytes);
+        true
+    });
+
+    //labels are only useful where a line starts
+    let labels: BTreeMap<u16, String> = targets.into_iter()
+        .filter(|(address, _)| (*address as usize) >= origin as usize && (*address as usize) < end)
+        .filter(|(address, _)| code.contains_key(address) || !code_bytes.contains(&(*address as usize)))
+        .map(|(address, kind)| (address, format!("{}_{:03x}", kind, address)))
+        .collect();
+
+    //the walk goes up to end, which is one past the last 16-bit address for a rom that fills memory
+    let mut lines = Vec::new();
+    let mut address = origin as usize;
+    while address < end {
+        let label = labels.get(&(address as u16)).cloned();
+        if let Some(instruction) = code.get(&(address as u16)) {
+            lines.push(Line { address: address as u16, label, item: Item::Code(*instruction) });
+            address += instruction.size() as usize;
+            continue;
+        }
+        let start = address;
+        let mut data = Vec::new();
+        while address < end && data.len() < DATA_PER_LINE && !code_bytes.contains(&address)
+            && (address == start || !labels.contains_key(&(address as u16))) {
+            data.push(bytes[address - origin as usize]);
+            address += 1;
+        }
+        lines.push(Line { address: start as u16, label, item: Item::Data(data) });
+    }
+    Ok(lines)
+}
+
+/// renders lines as assembler source, addresses and raw bytes go in comments
+pub fn listing(lines: &[Line]) -> String {
+    let labels: BTreeMap<u16, String> = lines.iter()
+        .filter_map(|line| line.label.clone().map(|label| (line.address, label)))
+        .collect();
+    let label = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| format!("{:#05x}", nnn));
+
+    let mut out = String::new();
+    for line in lines {
+        if let Some(l) = &line.label {
+            out += &format!("{}:\n", l);
+        }
+        let (text, raw) = match &line.item {
+            Item::Code(instruction) => {
+                let raw: Vec<String> = instruction.to_bytes().iter().map(|b| format!("{:02X}", b)).collect();
+                (mnemonic(instruction, &label), raw.concat())
+            },
+            Item::Data(bytes) => {
+                let values: Vec<String> = bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+                (format!("db {}", values.join(", ")), String::new())
+            }
+        };
+        let line = format!("    {:<24}; {:#05x} {}", text, line.address, raw);
+        out += line.trim_end();
+        out.push('\n');
+    }
+    out
+}
+
+/// disassembles a rom as it would be loaded at 0x200
+pub fn disassemble_rom(rom: &[u8]) -> Result<String, Chip8Error> {
+    Ok(listing(&disassemble(rom, 0x200)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_rom, Item};
+    use crate::error::Chip8Error;
+    use crate::instruction::Instruction;
+
+    #[test]
+    fn separates_code_from_data() {
+        // LD I, 0x206; CALL 0x208; JP 0x204 (self); data 0xF0 0x90; RET
+        let rom = [0xA2, 0x06, 0x22, 0x08, 0x12, 0x04, 0xF0, 0x90, 0x00, 0xEE];
+        let lines = disassemble(&rom, 0x200).unwrap();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[3].item, Item::Data(vec![0xF0, 0x90]));
+        assert_eq!(lines[3].label.as_deref(), Some("data_206"));
+        assert_eq!(lines[4].item, Item::Code(Instruction::Return));
+        assert_eq!(lines[4].label.as_deref(), Some("sub_208"));
+
+        let text = disassemble_rom(&rom).unwrap();
+        assert!(text.contains("    LD I, data_206"));
+        assert!(text.contains("    CALL sub_208"));
+        assert!(text.contains("loc_204:\n    JP loc_204"));
+        assert!(text.contains("    db 0xf0, 0x90"));
+    }
+
+    #[test]
+    fn roms_up_to_the_end_of_memory() {
+        //roms that fill xo-chip memory: code up to 0xfffe, data up to 0xffff
+        let mut rom = [0x60, 0x00].repeat(0x7F00);
+        let lines = disassemble(&rom, 0x200).unwrap();
+        assert_eq!(lines.last().map(|line| (line.address, &line.item)), Some((0xFFFE, &Item::Code(Instruction::LoadImm { x: 0, kk: 0 }))));
+        let lines = disassemble(&[0; 0xFE00], 0x200).unwrap();
+        assert_eq!(lines.last().map(|line| (line.address, &line.item)), Some((0xFFF8, &Item::Data(vec![0; 8]))));
+
+        rom.push(0);
+        assert!(matches!(disassemble(&rom, 0x200), Err(Chip8Error::RomTooLarge { size: 0xFE01, max: 0xFE00 })));
+    }
+}
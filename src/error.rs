@@ -0,0 +1,64 @@
+use std::{fmt, io};
+
+use crate::assembler::AsmError;
+
+/// everything that can go wrong while loading or running a rom
+/// addresses are the location of the faulting instruction
+#[derive(Debug)]
+pub enum Chip8Error {
+    InvalidOpcode { address: u16, opcode: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    MemoryOutOfBounds { address: usize },
+    RomTooLarge { size: usize, max: usize },
+    InvalidSaveState(String),
+    InvalidMovie(String),
+    InvalidArgument(String),
+    Image(String),
+    Audio(String),
+    Frontend(String),
+    Assembly(AsmError),
+    Io(io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { address, opcode } => write!(f, "invalid instruction {:#06x} at {:#05x}", opcode, address),
+            Chip8Error::StackOverflow { address } => write!(f, "stack overflow at {:#05x}", address),
+            Chip8Error::StackUnderflow { address } => write!(f, "return with an empty stack at {:#05x}", address),
+            Chip8Error::MemoryOutOfBounds { address } => write!(f, "memory access out of bounds at {:#05x}", address),
+            Chip8Error::RomTooLarge { size, max } => write!(f, "rom is {} bytes, at most {} fit in memory", size, max),
+            Chip8Error::InvalidSaveState(msg) => write!(f, "invalid save state: {}", msg),
+            Chip8Error::InvalidMovie(msg) => write!(f, "invalid movie: {}", msg),
+            Chip8Error::InvalidArgument(msg) => write!(f, "{}", msg),
+            Chip8Error::Image(msg) => write!(f, "could not write image: {}", msg),
+            Chip8Error::Audio(msg) => write!(f, "could not write audio: {}", msg),
+            Chip8Error::Frontend(msg) => write!(f, "frontend error: {}", msg),
+            Chip8Error::Assembly(e) => write!(f, "{}", e),
+            Chip8Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            Chip8Error::Assembly(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
+
+impl From<AsmError> for Chip8Error {
+    fn from(e: AsmError) -> Self {
+        Chip8Error::Assembly(e)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::chip8::Chip8Display;
+use crate::constants::DEFAULT_PALETTE;
+use crate::error::Chip8Error;
+
+/// events a keypad source reports to the run loop
+/// keys are the 16 chip8 keypad values 0x0 - 0xF
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyDown(u8),
+    KeyUp(u8),
+
+    //Leave the run loop
+    Quit,
+
+    //Run the game backwards while held
+    Rewind(bool),
+
+    //Write or restore the numbered save state slot
+    SaveState(u8),
+    LoadState(u8),
+
+    //Write the display to a png file
+    Screenshot,
+
+    //Start recording a clip, or stop the one being recorded
+    ToggleClip,
+
+    //Silence the buzzer, or let it be heard again
+    ToggleMute,
+
+    //Show the display in the next palette
+    CyclePalette,
+
+    //Switch between a window and fullscreen
+    ToggleFullscreen,
+
+    //The display has to be presented again, e.g. after the window was resized
+    Redraw,
+
+    //Debug mode controls
+    Step,
+    DumpMemory,
+    DumpRegisters,
+    ShowInstruction,
+}
+
+/// rgb colours for the four pixel values, the second XO-CHIP plane adds the upper two
+pub type Palette = [[u8; 3]; 4];
+
+/// anything that can show the chip8 framebuffer
+/// present is only called when the display has changed, an error ends the run
+pub trait DisplaySink {
+    fn present(&mut self, display: &Chip8Display) -> Result<(), Chip8Error>;
+
+    /// the colours pixels are shown in, screenshots use them too
+    fn palette(&self) -> Palette {
+        DEFAULT_PALETTE
+    }
+
+    /// switches to the next palette, displays with fixed colours ignore it
+    /// the display is presented again right after
+    fn cycle_palette(&mut self) {}
+
+    /// switches between a window and fullscreen, the display is presented again right after
+    fn toggle_fullscreen(&mut self) {}
+}
+
+/// anything that can make the chip8 beep
+/// start is called when the sound timer is set, stop when it runs out
+pub trait BuzzerSink {
+    fn start(&mut self);
+    fn stop(&mut self);
+
+    /// XO-CHIP: play 128 one-bit samples in a loop instead of a plain tone
+    /// pitch 64 is 4000 samples per second, buzzers that can't play patterns ignore it
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+
+    /// silences the buzzer or lets it be heard again, silent buzzers ignore it
+    fn toggle_mute(&mut self) {}
+}
+
+/// anything that can feed key presses to the run loop
+/// poll_events returns every event that arrived since the last call
+pub trait KeypadSource {
+    fn poll_events(&mut self) -> Vec<InputEvent>;
+}
+
+/// the set of backends a Chip8 runs against
+pub struct Frontend<'a> {
+    pub display: &'a mut dyn DisplaySink,
+    pub buzzer: &'a mut dyn BuzzerSink,
+    pub keypad: &'a mut dyn KeypadSource,
+}
+
+/// display that throws every frame away
+pub struct NullDisplay;
+
+impl DisplaySink for NullDisplay {
+    fn present(&mut self, _display: &Chip8Display) -> Result<(), Chip8Error> {
+        Ok(())
+    }
+}
+
+/// buzzer that stays silent
+pub struct NullBuzzer;
+
+impl BuzzerSink for NullBuzzer {
+    fn start(&mut self) {}
+    fn stop(&mut self) {}
+}
+
+/// keypad that never reports any key
+pub struct NullKeypad;
+
+impl KeypadSource for NullKeypad {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        Vec::new()
+    }
+}
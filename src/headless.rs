@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::Path;
+
+use crate::audio::WavRecorder;
+use crate::chip8::{self, Chip8, Chip8Display};
+use crate::error::Chip8Error;
+use crate::frontend::InputEvent;
+use crate::movie::{rom_hash, Movie};
+use crate::recorder::Recorder;
+use crate::screenshot;
+use crate::Config;
+
+/// how the final framebuffer of a headless run is written
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    //One character per pixel, see ASCII_PIXELS
+    Ascii,
+    //Plain PBM image, any lit plane counts as black
+    Pbm,
+    //FNV-1a hash of the size and every pixel colour, enough for golden-image comparisons
+    Hash,
+}
+
+/// characters for the four pixel colours in ascii output
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// seed used when none is given, so headless runs are reproducible by default
+pub const DEFAULT_HEADLESS_SEED: u64 = 0;
+
+impl OutputFormat {
+    /// the formats --output can write
+    pub const NAMES: [&'static str; 3] = ["ascii", "pbm", "hash"];
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "ascii" => Some(OutputFormat::Ascii),
+            "pbm" => Some(OutputFormat::Pbm),
+            "hash" => Some(OutputFormat::Hash),
+            _ => None
+        }
+    }
+
+    /// the display written in this format
+    pub fn render(&self, display: &Chip8Display) -> String {
+        match self {
+            OutputFormat::Ascii => ascii(display),
+            OutputFormat::Pbm => pbm(display),
+            OutputFormat::Hash => format!("{:016x}\n", hash(display)),
+        }
+    }
+}
+
+/// the visible part of the display, one line per row
+pub fn ascii(display: &Chip8Display) -> String {
+    let mut out = String::new();
+    for y in 0..display.height() {
+        out.extend((0..display.width()).map(|x| ASCII_PIXELS[display.color(x, y) as usize]));
+        out.push('\n');
+    }
+    out
+}
+
+/// the visible part of the display as a plain (P1) PBM image
+pub fn pbm(display: &Chip8Display) -> String {
+    let mut out = format!("P1\n{} {}\n", display.width(), display.height());
+    for y in 0..display.height() {
+        let row: Vec<&str> = (0..display.width()).map(|x| if display.pixel(x, y).is_on() { "1" } else { "0" }).collect();
+        out += &row.join(" ");
+        out.push('\n');
+    }
+    out
+}
+
+/// 64-bit FNV-1a over the width, height and colour of every visible pixel
+pub fn hash(display: &Chip8Display) -> u64 {
+    let size = [display.width() as u8, display.height() as u8];
+    let pixels = (0..display.height()).flat_map(|y| (0..display.width()).map(move |x| display.color(x, y)));
+    fnv1a(size.into_iter().chain(pixels))
+}
+
+/// 64-bit FNV-1a of bytes
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// one line with every register
+pub fn registers(chip: &Chip8) -> String {
+    let vx: Vec<String> = chip.Vx.iter().enumerate().map(|(i, v)| format!("V{:X}={:02x}", i, v)).collect();
+    format!("PC={:04x} I={:04x} SP={} DT={} ST={} {}", chip.PC, chip.I, chip.SP, chip.delay_timer, chip.sound_timer, vx.join(" "))
+}
+
+/// reads an input script: one `<frame> down|up <key>` per line, keys in hex, # starts a comment
+/// the events are returned in frame order
+pub fn parse_input_script(text: &str) -> Result<Vec<(u64, InputEvent)>, Chip8Error> {
+    let mut events = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || Chip8Error::InvalidArgument(format!("input script line {}: expected `<frame> down|up <key>`, got `{}`", number + 1, line));
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [frame, action, key] = parts[..] else {
+            return Err(invalid());
+        };
+        let frame: u64 = frame.parse().map_err(|_| invalid())?;
+        let key = u8::from_str_radix(key.trim_start_matches("0x"), 16).ok().filter(|&k| k < 16).ok_or_else(invalid)?;
+        let event = match action {
+            "down" => InputEvent::KeyDown(key),
+            "up" => InputEvent::KeyUp(key),
+            _ => return Err(invalid())
+        };
+        events.push((frame, event));
+    }
+    //stable, so events of the same frame keep their order
+    events.sort_by_key(|&(frame, _)| frame);
+    Ok(events)
+}
+
+/// runs the rom for the configured number of frames without a window,
+/// then prints the display in the chosen format and the registers
+/// with --out the display goes to that file instead of stdout, with --png it is also saved as an image
+/// with --clip every frame is recorded, with --wav the buzzer
+pub fn run_headless(mut cfg: Config) -> Result<(), Chip8Error> {
+    if cfg.record.is_some() {
+        return Err(Chip8Error::InvalidArgument(String::from("--record is not available with --headless, use an input script!")));
+    }
+    let rom = chip8::read_rom(&cfg.filename)?;
+    let movie = match &cfg.play {
+        Some(path) => {
+            let movie = Movie::load(Path::new(path))?;
+            movie.check_rom(&rom)?;
+            movie
+        },
+        None => {
+            let frames = cfg.frames.ok_or_else(|| Chip8Error::InvalidArgument(String::from("--headless needs --frames N!")))?;
+            let mut movie = Movie::new(rom_hash(&rom), cfg.quirks, cfg.seed.unwrap_or(DEFAULT_HEADLESS_SEED), cfg.instructions_per_frame);
+            movie.frames = frames;
+            if let Some(script) = &cfg.input_script {
+                movie.events = parse_input_script(&fs::read_to_string(script)?)?;
+            }
+            movie
+        }
+    };
+    cfg.quirks = movie.quirks;
+
+    let mut chip = Chip8::new(cfg.quirks);
+    chip.load_rom(&rom)?;
+    chip.PC = 0x200;
+    let mut recorder = match &cfg.clip {
+        Some(path) => Some(Recorder::start(Path::new(path), cfg.clip_scale, &cfg.palette)?),
+        None => None
+    };
+    let mut wav = match &cfg.wav {
+        Some(path) => Some(WavRecorder::start(Path::new(path), cfg.tone)?),
+        None => None
+    };
+    let result = movie.replay_with(&mut chip, |chip| {
+        if let Some(recorder) = &mut recorder {
+            recorder.capture(&chip.display)?;
+        }
+        if let Some(wav) = &mut wav {
+            wav.capture(chip)?;
+        }
+        Ok(())
+    });
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    let image = cfg.output.render(&chip.display);
+    match &cfg.output_file {
+        Some(path) => fs::write(path, image)?,
+        None => print!("{}", image)
+    }
+    if let Some(path) = &cfg.png_file {
+        screenshot::save_png(&chip.display, cfg.screenshot_scale, &cfg.palette, Path::new(path))?;
+    }
+    println!("{}", registers(&chip));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ascii, hash, parse_input_script, pbm};
+    use crate::chip8::Chip8;
+    use crate::frontend::InputEvent;
+
+    #[test]
+    fn renders_the_visible_display() {
+        let mut chip = Chip8::new_default();
+        chip.draw_sprite_in_mem_to_x_y(0x50, 62, 0, 1).unwrap();
+        let text = ascii(&chip.display);
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with("##..") && text.lines().next().unwrap().ends_with("##"));
+
+        let image = pbm(&chip.display);
+        assert!(image.starts_with("P1\n64 32\n1 1 0 0 "));
+
+        let before = hash(&chip.display);
+        chip.display.set_hires(true);
+        assert_ne!(before, hash(&chip.display));
+    }
+
+    #[test]
+    fn reads_input_scripts() {
+        let script = "# press 5 for a second\n60 down 5\n120 up 5  # let go\n\n30 down a\n";
+        assert_eq!(parse_input_script(script).unwrap(), vec![
+            (30, InputEvent::KeyDown(0xA)),
+            (60, InputEvent::KeyDown(5)),
+            (120, InputEvent::KeyUp(5)),
+        ]);
+        assert!(parse_input_script("60 press 5").is_err());
+        assert!(parse_input_script("60 down 16").is_err());
+    }
+}
@@ -1,246 +1,1189 @@
+use std::fmt;
 
-
-use crate::Chip8;
-use rand::Rng;
+use crate::chip8::Chip8;
+use crate::constants::BIG_FONT_LOCATION;
+use crate::error::Chip8Error;
 
 
 pub enum InstructionResult {
     BreakLoop,
-    StartDelayTimer,
-    StartSoundTimer,
-    SkipIfPressed(u8),
-    SkipIfNotPressed(u8),
-    WaitForKey(u8),
+    //the rest of the frame is skipped, see Quirks::display_wait
+    DisplayWait,
     Ok
 }
 
-impl Chip8 {
-    pub fn decode_execute_instruction(&mut self, instruction: u16) -> InstructionResult {
-        match instruction & 0xF000 {
-            0x0000 => {
-                if instruction == 0x00E0 {
-                    //clear display
+/// a decoded chip8 instruction
+/// x and y are register indexes, kk is a byte, nnn is an address and n a nibble
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    //00E0
+    Clear,
+    //00EE
+    Return,
+    //00CN, SUPER-CHIP
+    ScrollDown { n: u8 },
+    //00DN, XO-CHIP
+    ScrollUp { n: u8 },
+    //00FB, SUPER-CHIP
+    ScrollRight,
+    //00FC, SUPER-CHIP
+    ScrollLeft,
+    //00FD, SUPER-CHIP
+    Exit,
+    //00FE, SUPER-CHIP
+    LowRes,
+    //00FF, SUPER-CHIP
+    HighRes,
+    //1nnn
+    Jump { nnn: u16 },
+    //2nnn
+    Call { nnn: u16 },
+    //3xkk
+    SkipEqImm { x: u8, kk: u8 },
+    //4xkk
+    SkipNeImm { x: u8, kk: u8 },
+    //5xy0
+    SkipEqReg { x: u8, y: u8 },
+    //5xy2, XO-CHIP
+    SaveRange { x: u8, y: u8 },
+    //5xy3, XO-CHIP
+    LoadRange { x: u8, y: u8 },
+    //6xkk
+    LoadImm { x: u8, kk: u8 },
+    //7xkk
+    AddImm { x: u8, kk: u8 },
+    //8xy0
+    Load { x: u8, y: u8 },
+    //8xy1
+    Or { x: u8, y: u8 },
+    //8xy2
+    And { x: u8, y: u8 },
+    //8xy3
+    Xor { x: u8, y: u8 },
+    //8xy4
+    Add { x: u8, y: u8 },
+    //8xy5
+    Sub { x: u8, y: u8 },
+    //8xy6
+    ShiftRight { x: u8, y: u8 },
+    //8xy7
+    SubN { x: u8, y: u8 },
+    //8xyE
+    ShiftLeft { x: u8, y: u8 },
+    //9xy0
+    SkipNeReg { x: u8, y: u8 },
+    //Annn
+    LoadI { nnn: u16 },
+    //Bnnn
+    JumpV0 { nnn: u16 },
+    //Cxkk
+    Random { x: u8, kk: u8 },
+    //Dxyn, n = 0 draws a 16x16 sprite
+    Draw { x: u8, y: u8, n: u8 },
+    //Ex9E
+    SkipKeyPressed { x: u8 },
+    //ExA1
+    SkipKeyNotPressed { x: u8 },
+    //F000 nnnn, XO-CHIP, the only 4-byte instruction
+    LongLoadI { nnnn: u16 },
+    //Fn01, XO-CHIP
+    SelectPlanes { n: u8 },
+    //F002, XO-CHIP
+    LoadAudio,
+    //Fx07
+    LoadDelay { x: u8 },
+    //Fx0A
+    WaitKey { x: u8 },
+    //Fx15
+    SetDelay { x: u8 },
+    //Fx18
+    SetSound { x: u8 },
+    //Fx1E
+    AddI { x: u8 },
+    //Fx29
+    LoadFont { x: u8 },
+    //Fx30, SUPER-CHIP
+    LoadBigFont { x: u8 },
+    //Fx33
+    StoreBcd { x: u8 },
+    //Fx3A, XO-CHIP
+    SetPitch { x: u8 },
+    //Fx55
+    StoreRegs { x: u8 },
+    //Fx65
+    LoadRegs { x: u8 },
+    //Fx75, SUPER-CHIP
+    StoreFlags { x: u8 },
+    //Fx85, SUPER-CHIP
+    LoadFlags { x: u8 },
+}
+
+/// returned by decode for words that are not a known instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid instruction {:#06x}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// splits a 16-bit word into its instruction
+/// F000 needs the word after it, see decode_long
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    let instruction = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Clear,
+            0x00EE => Instruction::Return,
+            0x00C0..=0x00CF => Instruction::ScrollDown { n },
+            0x00D0..=0x00DF => Instruction::ScrollUp { n },
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowRes,
+            0x00FF => Instruction::HighRes,
+            _ => return Err(DecodeError { opcode })
+        },
+        0x1000 => Instruction::Jump { nnn },
+        0x2000 => Instruction::Call { nnn },
+        0x3000 => Instruction::SkipEqImm { x, kk },
+        0x4000 => Instruction::SkipNeImm { x, kk },
+        0x5000 => match n {
+            0x0 => Instruction::SkipEqReg { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => return Err(DecodeError { opcode })
+        },
+        0x6000 => Instruction::LoadImm { x, kk },
+        0x7000 => Instruction::AddImm { x, kk },
+        0x8000 => match n {
+            0x0 => Instruction::Load { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::Add { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubN { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => return Err(DecodeError { opcode })
+        },
+        0x9000 if n == 0 => Instruction::SkipNeReg { x, y },
+        0xA000 => Instruction::LoadI { nnn },
+        0xB000 => Instruction::JumpV0 { nnn },
+        0xC000 => Instruction::Random { x, kk },
+        0xD000 => Instruction::Draw { x, y, n },
+        0xE000 => match kk {
+            0x9E => Instruction::SkipKeyPressed { x },
+            0xA1 => Instruction::SkipKeyNotPressed { x },
+            _ => return Err(DecodeError { opcode })
+        },
+        0xF000 => match kk {
+            0x01 => Instruction::SelectPlanes { n: x },
+            0x02 if x == 0 => Instruction::LoadAudio,
+            0x07 => Instruction::LoadDelay { x },
+            0x0A => Instruction::WaitKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddI { x },
+            0x29 => Instruction::LoadFont { x },
+            0x30 => Instruction::LoadBigFont { x },
+            0x33 => Instruction::StoreBcd { x },
+            0x3A => Instruction::SetPitch { x },
+            0x55 => Instruction::StoreRegs { x },
+            0x65 => Instruction::LoadRegs { x },
+            0x75 => Instruction::StoreFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => return Err(DecodeError { opcode })
+        },
+        _ => return Err(DecodeError { opcode })
+    };
+    Ok(instruction)
+}
+
+/// like decode, but takes the following word as well for F000 nnnn
+pub fn decode_long(opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+    if opcode == 0xF000 {
+        return Ok(Instruction::LongLoadI { nnnn: next });
+    }
+    decode(opcode)
+}
 
-                    self.display.clear();
-                    self.draw_display_to_window();
-                } else if instruction == 0x00EE {
-                    //return from subroutine
+impl Instruction {
+    /// size of the instruction in bytes, 4 for F000 nnnn and 2 for everything else
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LongLoadI { .. } => 4,
+            _ => 2
+        }
+    }
+
+    /// the instruction as it is stored in memory, big-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Instruction::LongLoadI { nnnn } => vec![0xF0, 0x00, (nnnn >> 8) as u8, nnnn as u8],
+            _ => self.encode().to_be_bytes().to_vec()
+        }
+    }
+
+    /// the first 16-bit word of the instruction, decode(i.encode()) == Ok(i) for 2-byte instructions
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xkk = |base: u16, x: u8, kk: u8| base | (x as u16 & 0xF) << 8 | kk as u16;
+        match *self {
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call { nnn } => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipEqImm { x, kk } => xkk(0x3000, x, kk),
+            Instruction::SkipNeImm { x, kk } => xkk(0x4000, x, kk),
+            Instruction::SkipEqReg { x, y } => xy(0x5000, x, y),
+            Instruction::SaveRange { x, y } => xy(0x5002, x, y),
+            Instruction::LoadRange { x, y } => xy(0x5003, x, y),
+            Instruction::LoadImm { x, kk } => xkk(0x6000, x, kk),
+            Instruction::AddImm { x, kk } => xkk(0x7000, x, kk),
+            Instruction::Load { x, y } => xy(0x8000, x, y),
+            Instruction::Or { x, y } => xy(0x8001, x, y),
+            Instruction::And { x, y } => xy(0x8002, x, y),
+            Instruction::Xor { x, y } => xy(0x8003, x, y),
+            Instruction::Add { x, y } => xy(0x8004, x, y),
+            Instruction::Sub { x, y } => xy(0x8005, x, y),
+            Instruction::ShiftRight { x, y } => xy(0x8006, x, y),
+            Instruction::SubN { x, y } => xy(0x8007, x, y),
+            Instruction::ShiftLeft { x, y } => xy(0x800E, x, y),
+            Instruction::SkipNeReg { x, y } => xy(0x9000, x, y),
+            Instruction::LoadI { nnn } => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpV0 { nnn } => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random { x, kk } => xkk(0xC000, x, kk),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y) | (n as u16 & 0xF),
+            Instruction::SkipKeyPressed { x } => xkk(0xE000, x, 0x9E),
+            Instruction::SkipKeyNotPressed { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LongLoadI { .. } => 0xF000,
+            Instruction::SelectPlanes { n } => xkk(0xF000, n, 0x01),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::LoadDelay { x } => xkk(0xF000, x, 0x07),
+            Instruction::WaitKey { x } => xkk(0xF000, x, 0x0A),
+            Instruction::SetDelay { x } => xkk(0xF000, x, 0x15),
+            Instruction::SetSound { x } => xkk(0xF000, x, 0x18),
+            Instruction::AddI { x } => xkk(0xF000, x, 0x1E),
+            Instruction::LoadFont { x } => xkk(0xF000, x, 0x29),
+            Instruction::LoadBigFont { x } => xkk(0xF000, x, 0x30),
+            Instruction::StoreBcd { x } => xkk(0xF000, x, 0x33),
+            Instruction::SetPitch { x } => xkk(0xF000, x, 0x3A),
+            Instruction::StoreRegs { x } => xkk(0xF000, x, 0x55),
+            Instruction::LoadRegs { x } => xkk(0xF000, x, 0x65),
+            Instruction::StoreFlags { x } => xkk(0xF000, x, 0x75),
+            Instruction::LoadFlags { x } => xkk(0xF000, x, 0x85),
+        }
+    }
+}
+
+impl Chip8 {
+    /// decodes and executes a word, PC must already point past it
+    pub fn decode_execute_instruction(&mut self, instruction: u16) -> Result<InstructionResult, Chip8Error> {
+        match decode(instruction) {
+            Ok(i) => self.execute(i),
+            Err(e) => Err(Chip8Error::InvalidOpcode { address: self.PC.wrapping_sub(2), opcode: e.opcode })
+        }
+    }
 
-                    self.PC = self.stack[self.SP as usize];
-                    self.SP -= 1;
-                } else {
-                    println!("Invalid instruction at mem: {}, {:#04x}", self.PC, instruction)
+    /// executes a decoded instruction, PC must already point past it
+    pub fn execute(&mut self, instruction: Instruction) -> Result<InstructionResult, Chip8Error> {
+        match instruction {
+            Instruction::Clear => {
+                //clear display
+                self.display.clear();
+                self.draw_flag = true;
+            },
+            Instruction::Return => {
+                //return from subroutine
+                if self.SP == 0 {
+                    return Err(Chip8Error::StackUnderflow { address: self.PC.wrapping_sub(2) });
                 }
+                self.SP -= 1;
+                self.PC = self.stack[self.SP as usize];
+            },
+            Instruction::ScrollDown { n } => {
+                //scroll the display down by n rows
+                self.display.scroll_down(n as usize);
+                self.draw_flag = true;
+            },
+            Instruction::ScrollUp { n } => {
+                //scroll the display up by n rows
+                self.display.scroll_up(n as usize);
+                self.draw_flag = true;
+            },
+            Instruction::ScrollRight => {
+                //scroll the display right by 4 pixels
+                self.display.scroll_right(4);
+                self.draw_flag = true;
+            },
+            Instruction::ScrollLeft => {
+                //scroll the display left by 4 pixels
+                self.display.scroll_left(4);
+                self.draw_flag = true;
             },
-            0x1000 => {
+            Instruction::Exit => {
+                //exit the interpreter
+                self.PC = self.PC.wrapping_sub(2);
+                return Ok(InstructionResult::BreakLoop);
+            },
+            Instruction::LowRes => {
+                //switch to 64x32
+                self.display.set_hires(false);
+                self.draw_flag = true;
+            },
+            Instruction::HighRes => {
+                //switch to 128x64
+                self.display.set_hires(true);
+                self.draw_flag = true;
+            },
+            Instruction::Jump { nnn } => {
                 //set PC to nnn
-                if self.PC - 2 == instruction & 0x0FFF {
-                    return InstructionResult::BreakLoop;
+                //a jump to itself means the program is done
+                let halted = self.PC.wrapping_sub(2) == nnn;
+                self.PC = nnn;
+                if halted {
+                    return Ok(InstructionResult::BreakLoop);
                 }
-                self.PC = instruction & 0x0FFF;
             },
-            0x2000 => {
+            Instruction::Call { nnn } => {
                 //call subroutine at nnn
-
-                self.SP += 1;
+                if self.SP as usize == self.stack.len() {
+                    return Err(Chip8Error::StackOverflow { address: self.PC.wrapping_sub(2) });
+                }
                 self.stack[self.SP as usize] = self.PC;
-                self.PC = instruction & 0x0FFF;
+                self.SP += 1;
+                self.PC = nnn;
             },
-            0x3000 => {
-                //3xkk
+            Instruction::SkipEqImm { x, kk } => {
                 //if Vx == kk skip instruction
-                if self.Vx[((instruction & 0x0F00) >> 8) as usize] == ((instruction & 0x00FF) as u8) {
-                    self.PC += 2;
+                if self.Vx[x as usize] == kk {
+                    self.skip();
                 }
             },
-            0x4000 => {
-                //4xkk
+            Instruction::SkipNeImm { x, kk } => {
                 //if Vx != kk skip instruction
-                if self.Vx[((instruction & 0x0F00) >> 8) as usize] != ((instruction & 0x00FF) as u8) {
-                    self.PC += 2;
+                if self.Vx[x as usize] != kk {
+                    self.skip();
                 }
             },
-            0x5000 => {
-                //5xy0
+            Instruction::SkipEqReg { x, y } => {
                 //if Vx == Vy skip instruction
-                if instruction & 0x000F == 0 {
-                    if self.Vx[((instruction & 0x0F00) >> 8) as usize] == self.Vx[((instruction & 0x00F0) >> 4) as usize] {
-                        self.PC += 2;
-                    }
+                if self.Vx[x as usize] == self.Vx[y as usize] {
+                    self.skip();
+                }
+            },
+            Instruction::SaveRange { x, y } => {
+                //Store registers Vx through Vy in memory starting at location I, in reverse if x > y
+                let (x, y) = (x as usize, y as usize);
+                let count = x.abs_diff(y) + 1;
+                let start = self.mem_range(self.I as usize, count)?;
+                for i in 0..count {
+                    self.memory[start + i] = self.Vx[if x <= y { x + i } else { x - i }];
                 }
             },
-            0x6000 => {
-                //6xkk
+            Instruction::LoadRange { x, y } => {
+                //Read registers Vx through Vy from memory starting at location I, in reverse if x > y
+                let (x, y) = (x as usize, y as usize);
+                let count = x.abs_diff(y) + 1;
+                let start = self.mem_range(self.I as usize, count)?;
+                for i in 0..count {
+                    self.Vx[if x <= y { x + i } else { x - i }] = self.memory[start + i];
+                }
+            },
+            Instruction::LoadImm { x, kk } => {
                 //put value kk in register Vx
-                self.Vx[((instruction & 0x0F00) >> 8) as usize] = (instruction & 0x00FF) as u8;
-            },
-            0x7000 => {
-                //7xkk
-                //set Vx += kk
-                self.Vx[((instruction & 0x0F00) >> 8) as usize] = u8::wrapping_add(self.Vx[((instruction & 0x0F00) >> 8) as usize], (instruction & 0x00FF) as u8);
-            },
-            0x8000 => {
-                match instruction & 0x000F {
-                    0x0000 => {
-                        //8xy0
-                        //store the val of Vy in Vx
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x00F0) >> 4) as usize];
-                    },
-                    0x0001 => {
-                        //8xy1
-                        //Vx = Vx | Vy
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x0F00) >> 8) as usize] | self.Vx[((instruction & 0x00F0) >> 4) as usize]; 
-                    },
-                    0x0002 => {
-                        //8xy2
-                        //Vx = Vx & Vy
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x0F00) >> 8) as usize] & self.Vx[((instruction & 0x00F0) >> 4) as usize]; 
-                    },
-                    0x0003 => {
-                        //8xy3
-                        //Vx = Vx ^ Vy
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x0F00) >> 8) as usize] ^ self.Vx[((instruction & 0x00F0) >> 4) as usize]; 
-                    },
-                    0x0004 => {
-                        //8xy4
-                        //Vx = Vx + Vy, VF = carry
-                        let (result, of) = self.Vx[((instruction & 0x0F00) >> 8) as usize].overflowing_add(self.Vx[((instruction & 0x00F0)>> 4) as usize]);
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = result;
-                        if of {
-                            self.Vx[0xF] = 1;
-                        } else {
-                            self.Vx[0xF] = 0;
-                        }
-                    },
-                    0x0005 => {
-                        //8xy5
-                        //if Vx > Vy, Vf = 1, else Vf = 0. Vx = Vx - Vy
-                        if self.Vx[((instruction & 0x0F00) >> 8) as usize] > self.Vx[((instruction & 0x00F0) >> 4) as usize] {
-                            self.Vx[0x000F] = 1;
-                        } else {
-                            self.Vx[0x000F] = 0;
-                        }
-                        
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = u8::wrapping_sub(self.Vx[((instruction & 0x0F00) >> 8) as usize], self.Vx[((instruction & 0x00F0) >> 4) as usize]);
-                    },
-                    0x0006 => {
-                        //8xy6
-                        //Vf = Vx & 0x0001, Vx = Vx >> 1
-                        self.Vx[0x000F] = (self.Vx[((instruction & 0x0F00) >> 8) as usize] & 0x0001) as u8;
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x0F00) >> 8) as usize] >> 1; 
-                    },
-                    0x0007 => {
-                        //8vx7
-                        //if Vy > Vx, Vf = 1, else Vf = 0. Vx = Vy - Vx
-                        if self.Vx[((instruction & 0x0F00) >> 8) as usize] < self.Vx[((instruction & 0x00F0) >> 4) as usize] {
-                            self.Vx[0x000F] = 1;
-                        } else {
-                            self.Vx[0x000F] = 0;
-                        }
-                        self.Vx[((instruction & 0x00F0) >> 4) as usize] = u8::wrapping_sub(self.Vx[((instruction & 0x00F0) >> 8) as usize], self.Vx[((instruction & 0x0F00) >> 8) as usize]);
-                    },
-                    0x000E => {
-                        //8xyE
-                        //Vf = Vx & 0x1000, Vx = Vx << 1
-                        self.Vx[0x000F] = (self.Vx[((instruction & 0x0F00) >> 8) as usize] & 0x80) >> 7;
-                        self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.Vx[((instruction & 0x0F00) >> 8) as usize] << 1; 
-                    },
-                    _ => println!("Invalid instruction at mem: {}, {:#04x}", self.PC, instruction)
+                self.Vx[x as usize] = kk;
+            },
+            Instruction::AddImm { x, kk } => {
+                //set Vx += kk, no carry
+                self.Vx[x as usize] = self.Vx[x as usize].wrapping_add(kk);
+            },
+            Instruction::Load { x, y } => {
+                //store the val of Vy in Vx
+                self.Vx[x as usize] = self.Vx[y as usize];
+            },
+            Instruction::Or { x, y } => {
+                //Vx = Vx | Vy
+                self.Vx[x as usize] |= self.Vx[y as usize];
+                if self.quirks.vf_reset {
+                    self.Vx[0xF] = 0;
                 }
             },
-            0x9000 => {
-                //9xy0
+            Instruction::And { x, y } => {
+                //Vx = Vx & Vy
+                self.Vx[x as usize] &= self.Vx[y as usize];
+                if self.quirks.vf_reset {
+                    self.Vx[0xF] = 0;
+                }
+            },
+            Instruction::Xor { x, y } => {
+                //Vx = Vx ^ Vy
+                self.Vx[x as usize] ^= self.Vx[y as usize];
+                if self.quirks.vf_reset {
+                    self.Vx[0xF] = 0;
+                }
+            },
+            Instruction::Add { x, y } => {
+                //Vx = Vx + Vy, VF = carry
+                let (result, of) = self.Vx[x as usize].overflowing_add(self.Vx[y as usize]);
+                self.Vx[x as usize] = result;
+                self.Vx[0xF] = of as u8;
+            },
+            Instruction::Sub { x, y } => {
+                //if Vx >= Vy, Vf = 1, else Vf = 0. Vx = Vx - Vy
+                let not_borrow = self.Vx[x as usize] >= self.Vx[y as usize];
+                self.Vx[x as usize] = self.Vx[x as usize].wrapping_sub(self.Vx[y as usize]);
+                self.Vx[0xF] = not_borrow as u8;
+            },
+            Instruction::ShiftRight { x, y } => {
+                //Vf = Vx & 0x01, Vx = Vx >> 1
+                let source = if self.quirks.shift_uses_vy { self.Vx[y as usize] } else { self.Vx[x as usize] };
+                self.Vx[x as usize] = source >> 1;
+                self.Vx[0xF] = source & 0x01;
+            },
+            Instruction::SubN { x, y } => {
+                //if Vy >= Vx, Vf = 1, else Vf = 0. Vx = Vy - Vx
+                let not_borrow = self.Vx[y as usize] >= self.Vx[x as usize];
+                self.Vx[x as usize] = self.Vx[y as usize].wrapping_sub(self.Vx[x as usize]);
+                self.Vx[0xF] = not_borrow as u8;
+            },
+            Instruction::ShiftLeft { x, y } => {
+                //Vf = Vx & 0x80, Vx = Vx << 1
+                let source = if self.quirks.shift_uses_vy { self.Vx[y as usize] } else { self.Vx[x as usize] };
+                self.Vx[x as usize] = source << 1;
+                self.Vx[0xF] = (source & 0x80) >> 7;
+            },
+            Instruction::SkipNeReg { x, y } => {
                 //Skip next instruction if Vx != Vy.
-                if instruction & 0x0000 == 0 {
-                    if self.Vx[((instruction & 0x0F00) >> 8) as usize] != self.Vx[((instruction & 0x00F0) >> 4) as usize] {
-                        self.PC += 2;
-                    }
+                if self.Vx[x as usize] != self.Vx[y as usize] {
+                    self.skip();
                 }
             },
-            0xA000 => {
+            Instruction::LoadI { nnn } => {
                 //set I to nnn
-                self.I = instruction & 0x0FFF;
+                self.I = nnn;
             },
-            0xB000 => {
+            Instruction::JumpV0 { nnn } => {
                 //The program counter is set to nnn plus the value of V0.
-                self.PC = (instruction & 0x0FFF) + self.Vx[0] as u16; 
+                //With jump_uses_vx the high nibble of nnn picks the register instead
+                let offset = if self.quirks.jump_uses_vx { self.Vx[(nnn >> 8) as usize] } else { self.Vx[0] };
+                self.PC = nnn + offset as u16;
             },
-            0xC000 => {
-                //Cxkk
+            Instruction::Random { x, kk } => {
                 //Set Vx = random byte AND kk
-                self.Vx[((instruction & 0x0F00) >> 8) as usize] = rand::thread_rng().gen_range(0..=255) & ((instruction & 0x00FF) as u8); 
+                self.Vx[x as usize] = self.rng.next_byte() & kk;
             },
-            0xD000 => {
-                //Dxyn
+            Instruction::Draw { x, y, n } => {
                 //display the n-long sprite at location I to (Vx, Vy)
-                self.draw_sprite_in_mem_to_x_y(self.I as usize, self.Vx[((instruction & 0x0F00) >> 8) as usize] as usize, self.Vx[((instruction & 0x00F0) >> 4) as usize] as usize, (instruction & 0x000F) as usize);
-            },
-            0xE000 => {
-                if instruction & 0x009E == 0x009E {
-                    //Ex9E
-                    //Skip next instruction if key with value Vx is pressed
-                    return InstructionResult::SkipIfPressed(((instruction & 0x0F00) >> 8) as u8);
-                } else if instruction & 0x00A1 == 0x00A1 {
-                    //ExA1
-                    //Skip next instruction if key with value Vx is not pressed
-                    return InstructionResult::SkipIfNotPressed(((instruction & 0x0F00) >> 8) as u8);
-                } else {
-                    println!("Invalid instruction at mem: {}, {:#04x}", self.PC, instruction)
-                }
-            },
-            0xF000 => {
-                if instruction & 0x00FF == 0x0007 {
-                    //Fx07
-                    //set  Vx = delay timer
-                    self.Vx[((instruction & 0x0F00) >> 8) as usize] = self.delay_timer;
-                } else if instruction & 0x00FF == 0x000A {
-                    // Wait for a key press, store the value of the key in Vx.
-                    // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                    return InstructionResult::WaitForKey(((instruction & 0x0F00) >> 8) as u8);
-                } else if instruction & 0x00FF == 0x0015 {
-                    // Fx15
-                    // set DT = Vx
-                    self.delay_timer = self.Vx[((instruction & 0x0F00) >> 8) as usize];
-                    return InstructionResult::StartDelayTimer;
-                } else if instruction & 0x00FF == 0x0018 {
-                    // Fx18
-                    // Set sound timer = Vx
-                    self.sound_timer = self.Vx[((instruction & 0x0F00) >> 8) as usize];
-                    return InstructionResult::StartSoundTimer;
-                } else if instruction & 0x00FF == 0x001E {
-                    //Fx1E
-                    //set I += Vx
-                    self.I += self.Vx[((instruction & 0x0F00) >> 8) as usize] as u16;
-                } else if instruction & 0x00FF == 0x0029 {
-                    //Fx29
-                    // I = location of sprite for hexadecimal x
-                    self.I = 0x50 + (5 * ((instruction & 0x0F00) >> 8));
-                } else if instruction & 0x00FF == 0x0033 {
-                    //Fx33
-                    //Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                    let num = self.Vx[((instruction & 0x0F00) >> 8) as usize];
-                    self.memory[self.I as usize] = num / 100;
-                    self.memory[(self.I + 1) as usize] = (num / 10) % 10;
-                    self.memory[(self.I + 2) as usize] = num % 10;
-                } else if instruction & 0x00FF == 0x0055 {
-                    //Fx55
-                    //Store registers V0 through Vx in memory starting at location I
-                    for i in 0..=((instruction & 0x0F00) >> 8) {
-                        self.memory[(self.I + i) as usize] = self.Vx[i as usize];
-                    }
-                } else if instruction & 0x00FF == 0x0065 {
-                    //Fx65
-                    //Read registers V0 through Vx from memory starting at location I.
-                    for i in 0..=((instruction & 0x0F00) >> 8) {
-                        self.Vx[i as usize] = self.memory[(self.I + i) as usize];
-                    }
-
-                } else {
-                    println!("Invalid instruction at mem: {}, {:#04x}", self.PC, instruction)
-                }
-            },
-            _ => {println!("Invalid instruction at mem: {}, {:#04x}", self.PC, instruction)}
-        }
-        InstructionResult::Ok
-    }
-}
\ No newline at end of file
+                self.draw_sprite_in_mem_to_x_y(self.I as usize, self.Vx[x as usize] as usize, self.Vx[y as usize] as usize, n as usize)?;
+                if self.quirks.display_wait {
+                    return Ok(InstructionResult::DisplayWait);
+                }
+            },
+            Instruction::SkipKeyPressed { x } => {
+                //Skip next instruction if key with value Vx is pressed
+                if self.keypad.is_pressed(self.Vx[x as usize]) {
+                    self.skip();
+                }
+            },
+            Instruction::SkipKeyNotPressed { x } => {
+                //Skip next instruction if key with value Vx is not pressed
+                if !self.keypad.is_pressed(self.Vx[x as usize]) {
+                    self.skip();
+                }
+            },
+            Instruction::LongLoadI { nnnn } => {
+                //set I to the 16-bit word after the instruction
+                self.I = nnnn;
+            },
+            Instruction::SelectPlanes { n } => {
+                //select the bitplanes drawing, clearing and scrolling apply to
+                self.display.selected_planes = n & 0x3;
+            },
+            Instruction::LoadAudio => {
+                //load the 16-byte audio pattern at I
+                let start = self.mem_range(self.I as usize, 16)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[start..start + 16]);
+                self.audio_pattern = Some(pattern);
+                self.audio_flag = true;
+            },
+            Instruction::LoadDelay { x } => {
+                //set  Vx = delay timer
+                self.Vx[x as usize] = self.delay_timer;
+            },
+            Instruction::WaitKey { x } => {
+                // Wait for a key press, store the value of the key in Vx.
+                // All execution stops until a key is pressed and released, then the value of that key is stored in Vx.
+                // The instruction is repeated until then, releases from before the wait are ignored.
+                if !self.waiting_for_key {
+                    self.waiting_for_key = true;
+                    self.keypad.take_released();
+                }
+                match self.keypad.take_released() {
+                    Some(k) => {
+                        self.Vx[x as usize] = k;
+                        self.waiting_for_key = false;
+                    },
+                    None => self.PC = self.PC.wrapping_sub(2)
+                }
+            },
+            Instruction::SetDelay { x } => {
+                // set DT = Vx
+                self.delay_timer = self.Vx[x as usize];
+            },
+            Instruction::SetSound { x } => {
+                // Set sound timer = Vx
+                self.sound_timer = self.Vx[x as usize];
+            },
+            Instruction::AddI { x } => {
+                //set I += Vx
+                self.I = self.I.wrapping_add(self.Vx[x as usize] as u16);
+            },
+            Instruction::LoadFont { x } => {
+                // I = location of sprite for hexadecimal x
+                self.I = 0x50 + 5 * (self.Vx[x as usize] & 0xF) as u16;
+            },
+            Instruction::LoadBigFont { x } => {
+                // I = location of the 10-byte sprite for hexadecimal Vx
+                self.I = BIG_FONT_LOCATION as u16 + 10 * (self.Vx[x as usize] & 0xF) as u16;
+            },
+            Instruction::StoreBcd { x } => {
+                //Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                let num = self.Vx[x as usize];
+                let i = self.mem_range(self.I as usize, 3)?;
+                self.memory[i] = num / 100;
+                self.memory[i + 1] = (num / 10) % 10;
+                self.memory[i + 2] = num % 10;
+            },
+            Instruction::SetPitch { x } => {
+                //set the audio pattern playback rate to 4000*2^((Vx-64)/48) bits per second
+                self.pitch = self.Vx[x as usize];
+                self.audio_flag = true;
+            },
+            Instruction::StoreRegs { x } => {
+                //Store registers V0 through Vx in memory starting at location I
+                let start = self.mem_range(self.I as usize, x as usize + 1)?;
+                for i in 0..=x as usize {
+                    self.memory[start + i] = self.Vx[i];
+                }
+                self.I = self.I.wrapping_add(self.quirks.load_store_index.amount(x));
+            },
+            Instruction::LoadRegs { x } => {
+                //Read registers V0 through Vx from memory starting at location I.
+                let start = self.mem_range(self.I as usize, x as usize + 1)?;
+                for i in 0..=x as usize {
+                    self.Vx[i] = self.memory[start + i];
+                }
+                self.I = self.I.wrapping_add(self.quirks.load_store_index.amount(x));
+            },
+            Instruction::StoreFlags { x } => {
+                //Store registers V0 through Vx in the RPL user flags
+                self.rpl_flags[..=x as usize].copy_from_slice(&self.Vx[..=x as usize]);
+            },
+            Instruction::LoadFlags { x } => {
+                //Read registers V0 through Vx from the RPL user flags
+                self.Vx[..=x as usize].copy_from_slice(&self.rpl_flags[..=x as usize]);
+            }
+        }
+        Ok(InstructionResult::Ok)
+    }
+
+    /// skips the instruction at PC, which may be the 4-byte F000 nnnn with XO-CHIP memory
+    fn skip(&mut self) {
+        let long = self.quirks.extended_memory && self.fetch().is_ok_and(|opcode| opcode == 0xF000);
+        self.PC = self.PC.wrapping_add(if long { 4 } else { 2 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_long, DecodeError, Instruction};
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(decode(0x7A1F), Ok(Instruction::AddImm { x: 0xA, kk: 0x1F }));
+        assert_eq!(decode(0xD125), Ok(Instruction::Draw { x: 0x1, y: 0x2, n: 0x5 }));
+        assert_eq!(decode(0x2345), Ok(Instruction::Call { nnn: 0x345 }));
+        assert_eq!(decode(0x8AB7), Ok(Instruction::SubN { x: 0xA, y: 0xB }));
+        assert_eq!(decode(0xF365), Ok(Instruction::LoadRegs { x: 0x3 }));
+    }
+
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_words() {
+        for opcode in [0x0123, 0x00FA, 0x5121, 0x8128, 0x9121, 0xE1FF, 0xF1FF, 0xF000, 0xF102] {
+            assert_eq!(decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+
+    #[test]
+    fn long_load_takes_two_words() {
+        let long = decode_long(0xF000, 0x1234).unwrap();
+        assert_eq!(long, Instruction::LongLoadI { nnnn: 0x1234 });
+        assert_eq!(long.size(), 4);
+        assert_eq!(long.to_bytes(), vec![0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(decode_long(0x00E0, 0xF000), Ok(Instruction::Clear));
+    }
+}
+
+/// every opcode executed on a headless machine, checked against registers, memory and the framebuffer
+#[cfg(test)]
+mod conformance {
+    use super::InstructionResult;
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::quirks::Quirks;
+    use crate::random::ScriptedRandom;
+    use crate::savestate;
+
+    fn machine(quirks: Quirks) -> Chip8 {
+        let mut chip = Chip8::new(quirks);
+        chip.PC = 0x200;
+        chip
+    }
+
+    /// places opcode at PC and runs it the way the run loop does
+    fn exec(chip: &mut Chip8, opcode: u16) -> InstructionResult {
+        try_exec(chip, opcode).unwrap()
+    }
+
+    fn try_exec(chip: &mut Chip8, opcode: u16) -> Result<InstructionResult, Chip8Error> {
+        let pc = chip.PC as usize;
+        chip.memory[pc..pc + 2].copy_from_slice(&opcode.to_be_bytes());
+        chip.step()
+    }
+
+    /// coordinates of the lit pixels on the visible part of the screen
+    fn lit(chip: &Chip8) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..chip.display.height() {
+            for x in 0..chip.display.width() {
+                if chip.display.pixel(x, y).is_on() {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn jumps_calls_and_returns() {
+        let mut chip = machine(Quirks::MODERN);
+        exec(&mut chip, 0x1234);
+        assert_eq!(chip.PC, 0x234);
+
+        // 2nnn pushes the return address, 00EE pops it
+        exec(&mut chip, 0x2300);
+        assert_eq!((chip.PC, chip.SP, chip.stack[0]), (0x300, 1, 0x236));
+        exec(&mut chip, 0x2400);
+        assert_eq!((chip.PC, chip.SP, chip.stack[1]), (0x400, 2, 0x302));
+        exec(&mut chip, 0x00EE);
+        assert_eq!((chip.PC, chip.SP), (0x302, 1));
+        exec(&mut chip, 0x00EE);
+        assert_eq!((chip.PC, chip.SP), (0x236, 0));
+
+        // a jump to itself halts
+        chip.PC = 0x250;
+        assert!(matches!(exec(&mut chip, 0x1250), InstructionResult::BreakLoop));
+        assert_eq!(chip.PC, 0x250);
+    }
+
+    #[test]
+    fn stack_faults_leave_pc_on_the_instruction() {
+        let mut chip = machine(Quirks::MODERN);
+        assert!(matches!(try_exec(&mut chip, 0x00EE), Err(Chip8Error::StackUnderflow { address: 0x200 })));
+        assert_eq!(chip.PC, 0x200);
+
+        for _ in 0..16 {
+            exec(&mut chip, 0x2200);
+        }
+        assert_eq!(chip.SP, 16);
+        assert!(matches!(try_exec(&mut chip, 0x2200), Err(Chip8Error::StackOverflow { address: 0x200 })));
+        assert_eq!((chip.PC, chip.SP), (0x200, 16));
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[0] = 4;
+        chip.Vx[3] = 9;
+        exec(&mut chip, 0xB310);
+        assert_eq!(chip.PC, 0x314);
+
+        // Bxnn adds Vx instead of V0
+        let mut chip = machine(Quirks::CHIP_48);
+        chip.Vx[0] = 4;
+        chip.Vx[3] = 9;
+        exec(&mut chip, 0xB310);
+        assert_eq!(chip.PC, 0x319);
+    }
+
+    #[test]
+    fn conditional_skips() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[1] = 0x42;
+        chip.Vx[2] = 0x42;
+        chip.Vx[3] = 0x07;
+        let cases = [
+            (0x3142, 4), (0x3143, 2),
+            (0x4142, 2), (0x4143, 4),
+            (0x5120, 4), (0x5130, 2),
+            (0x9120, 2), (0x9130, 4),
+        ];
+        for (opcode, advance) in cases {
+            chip.PC = 0x200;
+            exec(&mut chip, opcode);
+            assert_eq!(chip.PC, 0x200 + advance, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn skips_step_over_long_loads() {
+        let mut chip = machine(Quirks::XO_CHIP);
+        chip.memory[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+        exec(&mut chip, 0x3000);
+        assert_eq!(chip.PC, 0x206);
+    }
+
+    #[test]
+    fn register_loads_and_adds() {
+        let mut chip = machine(Quirks::MODERN);
+        exec(&mut chip, 0x6A42);
+        assert_eq!(chip.Vx[0xA], 0x42);
+
+        // 7xkk wraps and leaves VF alone
+        chip.Vx[0xF] = 5;
+        exec(&mut chip, 0x7AC0);
+        assert_eq!((chip.Vx[0xA], chip.Vx[0xF]), (0x02, 5));
+
+        exec(&mut chip, 0x8BA0);
+        assert_eq!(chip.Vx[0xB], 0x02);
+    }
+
+    #[test]
+    fn logic_operations_and_vf_reset() {
+        for (quirks, vf) in [(Quirks::MODERN, 9), (Quirks::COSMAC_VIP, 0)] {
+            let mut chip = machine(quirks);
+            for (opcode, expected) in [(0x8011, 0b1110), (0x8012, 0b1000), (0x8013, 0b0110)] {
+                chip.Vx[0] = 0b1100;
+                chip.Vx[1] = 0b1010;
+                chip.Vx[0xF] = 9;
+                exec(&mut chip, opcode);
+                assert_eq!(chip.Vx[0], expected, "{:04X}", opcode);
+                assert_eq!(chip.Vx[0xF], vf, "{:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn add_and_subtract_set_the_flag() {
+        let mut chip = machine(Quirks::MODERN);
+        // (opcode, Vx, Vy, result, VF)
+        let cases = [
+            (0x8014, 0xF0, 0x20, 0x10, 1),
+            (0x8014, 0x10, 0x20, 0x30, 0),
+            (0x8015, 0x30, 0x10, 0x20, 1),
+            (0x8015, 0x10, 0x10, 0x00, 1),
+            (0x8015, 0x10, 0x30, 0xE0, 0),
+            (0x8017, 0x10, 0x30, 0x20, 1),
+            (0x8017, 0x30, 0x30, 0x00, 1),
+            (0x8017, 0x30, 0x10, 0xE0, 0),
+        ];
+        for (opcode, vx, vy, result, vf) in cases {
+            chip.Vx[0] = vx;
+            chip.Vx[1] = vy;
+            exec(&mut chip, opcode);
+            assert_eq!((chip.Vx[0], chip.Vx[0xF]), (result, vf), "{:04X} {:#x} {:#x}", opcode, vx, vy);
+        }
+
+        // with VF as the destination the flag wins
+        chip.Vx[0xF] = 0xFF;
+        chip.Vx[1] = 1;
+        exec(&mut chip, 0x8F14);
+        assert_eq!(chip.Vx[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_follow_the_quirk() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[0] = 0b1000_0011;
+        chip.Vx[1] = 0b0100_0000;
+        exec(&mut chip, 0x8016);
+        assert_eq!((chip.Vx[0], chip.Vx[0xF]), (0b0100_0001, 1));
+        exec(&mut chip, 0x801E);
+        assert_eq!((chip.Vx[0], chip.Vx[0xF]), (0b1000_0010, 0));
+        exec(&mut chip, 0x801E);
+        assert_eq!((chip.Vx[0], chip.Vx[0xF]), (0b0000_0100, 1));
+
+        // the VIP and Octo's XO-CHIP shift Vy into Vx
+        for quirks in [Quirks::COSMAC_VIP, Quirks::XO_CHIP] {
+            let mut chip = machine(quirks);
+            chip.Vx[0] = 0xFF;
+            chip.Vx[1] = 0b0000_0011;
+            exec(&mut chip, 0x8016);
+            assert_eq!((chip.Vx[0], chip.Vx[1], chip.Vx[0xF]), (0b0000_0001, 0b0000_0011, 1));
+            chip.Vx[1] = 0b1000_0001;
+            exec(&mut chip, 0x801E);
+            assert_eq!((chip.Vx[0], chip.Vx[0xF]), (0b0000_0010, 1));
+        }
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.set_random_source(Box::new(ScriptedRandom::new(&[0xA5, 0x3C])));
+        exec(&mut chip, 0xC40F);
+        exec(&mut chip, 0xC5F0);
+        assert_eq!((chip.Vx[4], chip.Vx[5]), (0x05, 0x30));
+    }
+
+    #[test]
+    fn seeded_random_repeats_across_a_state_load() {
+        let mut a = machine(Quirks::MODERN);
+        let mut b = machine(Quirks::MODERN);
+        a.seed(7);
+        b.seed(7);
+        for _ in 0..8 {
+            exec(&mut a, 0xC0FF);
+            exec(&mut b, 0xC0FF);
+            assert_eq!(a.Vx[0], b.Vx[0]);
+        }
+
+        //loading a state doesn't restart the sequence
+        a.restore(savestate::load_state(&savestate::save_state(&a)).unwrap());
+        exec(&mut a, 0xC0FF);
+        exec(&mut b, 0xC0FF);
+        assert_eq!(a.Vx[0], b.Vx[0]);
+    }
+
+    #[test]
+    fn index_register() {
+        let mut chip = machine(Quirks::MODERN);
+        exec(&mut chip, 0xA123);
+        assert_eq!(chip.I, 0x123);
+        chip.Vx[2] = 0x10;
+        chip.Vx[0xF] = 7;
+        exec(&mut chip, 0xF21E);
+        assert_eq!((chip.I, chip.Vx[0xF]), (0x133, 7));
+
+        chip.Vx[3] = 0xB;
+        exec(&mut chip, 0xF329);
+        assert_eq!(chip.I, 0x50 + 5 * 0xB);
+        exec(&mut chip, 0xF330);
+        assert_eq!(chip.I, 0xA0 + 10 * 0xB);
+        assert_eq!(chip.memory[chip.I as usize..chip.I as usize + 10], crate::constants::BIG_FONT[110..120]);
+
+        let mut chip = machine(Quirks::XO_CHIP);
+        chip.memory[0x202..0x204].copy_from_slice(&[0xBE, 0xEF]);
+        exec(&mut chip, 0xF000);
+        assert_eq!((chip.I, chip.PC), (0xBEEF, 0x204));
+    }
+
+    #[test]
+    fn bcd_and_register_dumps() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[7] = 254;
+        chip.I = 0x300;
+        exec(&mut chip, 0xF733);
+        assert_eq!(chip.memory[0x300..0x303], [2, 5, 4]);
+
+        chip.Vx[..4].copy_from_slice(&[1, 2, 3, 4]);
+        exec(&mut chip, 0xF355);
+        assert_eq!(chip.memory[0x300..0x304], [1, 2, 3, 4]);
+        assert_eq!(chip.I, 0x300);
+        chip.Vx[..4].copy_from_slice(&[0; 4]);
+        exec(&mut chip, 0xF365);
+        assert_eq!(chip.Vx[..4], [1, 2, 3, 4]);
+        assert_eq!(chip.I, 0x300);
+
+        // the VIP leaves I past the last register
+        let mut chip = machine(Quirks::COSMAC_VIP);
+        chip.I = 0x300;
+        exec(&mut chip, 0xF255);
+        assert_eq!(chip.I, 0x303);
+        exec(&mut chip, 0xF065);
+        assert_eq!(chip.I, 0x304);
+
+        // CHIP-48 leaves it on the last one
+        let mut chip = machine(Quirks::CHIP_48);
+        chip.I = 0x300;
+        exec(&mut chip, 0xF255);
+        assert_eq!(chip.I, 0x302);
+        exec(&mut chip, 0xF065);
+        assert_eq!(chip.I, 0x302);
+    }
+
+    #[test]
+    fn register_ranges_and_flags() {
+        let mut chip = machine(Quirks::XO_CHIP);
+        chip.Vx[2..6].copy_from_slice(&[0xA, 0xB, 0xC, 0xD]);
+        chip.I = 0x400;
+        exec(&mut chip, 0x5252);
+        assert_eq!(chip.memory[0x400..0x404], [0xA, 0xB, 0xC, 0xD]);
+        exec(&mut chip, 0x5522);
+        assert_eq!(chip.memory[0x400..0x404], [0xD, 0xC, 0xB, 0xA]);
+        exec(&mut chip, 0x5893);
+        assert_eq!(chip.Vx[8..10], [0xD, 0xC]);
+        assert_eq!(chip.I, 0x400);
+
+        chip.Vx[..3].copy_from_slice(&[7, 8, 9]);
+        exec(&mut chip, 0xF275);
+        chip.Vx[..3].copy_from_slice(&[0, 0, 0]);
+        exec(&mut chip, 0xF185);
+        assert_eq!(chip.Vx[..3], [7, 8, 0]);
+    }
+
+    #[test]
+    fn timers() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[1] = 30;
+        exec(&mut chip, 0xF115);
+        exec(&mut chip, 0xF118);
+        assert_eq!((chip.delay_timer, chip.sound_timer), (30, 30));
+        chip.tick_timers();
+        exec(&mut chip, 0xF207);
+        assert_eq!(chip.Vx[2], 29);
+
+        // a frame ticks them once however many instructions it runs
+        let mut chip = machine(Quirks::MODERN);
+        // V0 = 5, DT = V0, ST = V0, then loop forever at 0x208
+        let rom = [0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x08, 0x12, 0x08];
+        chip.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        chip.run_frame(4).unwrap();
+        assert_eq!((chip.delay_timer, chip.sound_timer), (4, 4));
+        for _ in 0..10 {
+            chip.run_frame(4).unwrap();
+        }
+        assert_eq!((chip.delay_timer, chip.sound_timer), (0, 0));
+    }
+
+    #[test]
+    fn keys() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.Vx[0] = 0xC;
+        exec(&mut chip, 0xE09E);
+        assert_eq!(chip.PC, 0x202);
+        exec(&mut chip, 0xE0A1);
+        assert_eq!(chip.PC, 0x206);
+        chip.keypad.press(0xC);
+        exec(&mut chip, 0xE09E);
+        assert_eq!(chip.PC, 0x20A);
+        exec(&mut chip, 0xE0A1);
+        assert_eq!(chip.PC, 0x20C);
+
+        // Fx0A repeats until a key goes down and up again, a key released before it doesn't count
+        chip.PC = 0x200;
+        chip.keypad.press(0x7);
+        chip.keypad.release(0x7);
+        exec(&mut chip, 0xF30A);
+        assert_eq!(chip.PC, 0x200);
+        chip.keypad.release(0xC);
+        exec(&mut chip, 0xF30A);
+        assert_eq!((chip.PC, chip.Vx[3]), (0x202, 0xC));
+    }
+
+    #[test]
+    fn drawing_and_clearing() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.memory[0x300..0x302].copy_from_slice(&[0b1100_0000, 0b0100_0000]);
+        chip.I = 0x300;
+        chip.Vx[0] = 10;
+        chip.Vx[1] = 5;
+        exec(&mut chip, 0xD012);
+        assert_eq!(lit(&chip), vec![(10, 5), (11, 5), (11, 6)]);
+        assert_eq!(chip.Vx[0xF], 0);
+        assert!(chip.draw_flag);
+
+        chip.Vx[0] = 11;
+        exec(&mut chip, 0xD011);
+        assert_eq!(lit(&chip), vec![(10, 5), (12, 5), (11, 6)]);
+        assert_eq!(chip.Vx[0xF], 1);
+
+        chip.draw_flag = false;
+        exec(&mut chip, 0x00E0);
+        assert!(lit(&chip).is_empty());
+        assert!(chip.draw_flag);
+    }
+
+    #[test]
+    fn sprites_wrap_or_clip() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.memory[0x300] = 0xFF;
+        chip.I = 0x300;
+        chip.Vx[0] = 60;
+        chip.Vx[1] = 31;
+        exec(&mut chip, 0xD011);
+        assert_eq!(lit(&chip), (0..4).chain(60..64).map(|x| (x, 31)).collect::<Vec<_>>());
+
+        // the origin wraps, (124, 95) is (60, 31), but the rest of the sprite is clipped
+        let mut chip = machine(Quirks::COSMAC_VIP);
+        chip.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+        chip.I = 0x300;
+        chip.Vx[0] = 124;
+        chip.Vx[1] = 95;
+        exec(&mut chip, 0xD012);
+        assert_eq!(lit(&chip), (60..64).map(|x| (x, 31)).collect::<Vec<_>>());
+        assert_eq!(chip.Vx[0xF], 0);
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        let mut chip = machine(Quirks::COSMAC_VIP);
+        chip.I = 0x50;
+        assert!(matches!(exec(&mut chip, 0xD005), InstructionResult::DisplayWait));
+        let mut chip = machine(Quirks::MODERN);
+        chip.I = 0x50;
+        assert!(matches!(exec(&mut chip, 0xD005), InstructionResult::Ok));
+    }
+
+    #[test]
+    fn big_sprites_follow_the_quirk() {
+        for (quirks, lit_pixels) in [(Quirks::SUPER_CHIP, 256), (Quirks::COSMAC_VIP, 0), (Quirks::CHIP_48, 0)] {
+            let mut chip = machine(quirks);
+            chip.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+            chip.I = 0x300;
+            exec(&mut chip, 0xD000);
+            assert_eq!((lit(&chip).len(), chip.Vx[0xF]), (lit_pixels, 0), "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn super_chip_screen() {
+        let mut chip = machine(Quirks::SUPER_CHIP);
+        exec(&mut chip, 0x00FF);
+        assert_eq!((chip.display.width(), chip.display.height()), (128, 64));
+        chip.memory[0x300] = 0x80;
+        chip.I = 0x300;
+        chip.Vx[0] = 8;
+        exec(&mut chip, 0xD011);
+
+        exec(&mut chip, 0x00C3);
+        assert_eq!(lit(&chip), vec![(8, 3)]);
+        exec(&mut chip, 0x00FB);
+        assert_eq!(lit(&chip), vec![(12, 3)]);
+        exec(&mut chip, 0x00FC);
+        exec(&mut chip, 0x00FC);
+        assert_eq!(lit(&chip), vec![(4, 3)]);
+
+        // 16x16 sprites are clipped at the right edge like 8 pixel wide ones
+        exec(&mut chip, 0x00E0);
+        chip.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        chip.Vx[0] = 120;
+        chip.Vx[1] = 0;
+        exec(&mut chip, 0xD010);
+        assert_eq!(lit(&chip), (0..16).flat_map(|y| (120..128).map(move |x| (x, y))).collect::<Vec<_>>());
+
+        exec(&mut chip, 0x00FE);
+        assert_eq!((chip.display.width(), chip.display.height()), (64, 32));
+        assert!(lit(&chip).is_empty());
+
+        chip.PC = 0x200;
+        assert!(matches!(exec(&mut chip, 0x00FD), InstructionResult::BreakLoop));
+        assert_eq!(chip.PC, 0x200);
+    }
+
+    #[test]
+    fn xo_chip_planes_scrolling_and_audio() {
+        let mut chip = machine(Quirks::XO_CHIP);
+        assert_eq!(chip.memory.len(), 0x10000);
+        chip.memory[0x300..0x302].copy_from_slice(&[0x80, 0x80]);
+        chip.I = 0x300;
+        exec(&mut chip, 0xF201);
+        exec(&mut chip, 0xD001);
+        assert_eq!((chip.display.color(0, 0), chip.Vx[0xF]), (2, 0));
+        exec(&mut chip, 0xF301);
+        exec(&mut chip, 0xD001);
+        assert_eq!((chip.display.color(0, 0), chip.Vx[0xF]), (1, 1));
+
+        // only the selected plane scrolls
+        exec(&mut chip, 0xF101);
+        exec(&mut chip, 0x00C2);
+        assert_eq!((chip.display.color(0, 0), chip.display.color(0, 2)), (0, 1));
+        exec(&mut chip, 0x00D1);
+        assert_eq!(chip.display.color(0, 1), 1);
+
+        // and so does clearing
+        exec(&mut chip, 0xF201);
+        exec(&mut chip, 0xD001);
+        exec(&mut chip, 0xF101);
+        exec(&mut chip, 0x00E0);
+        assert_eq!((chip.display.color(0, 0), chip.display.color(0, 1)), (2, 0));
+
+        chip.I = 0x300;
+        exec(&mut chip, 0xF002);
+        assert_eq!(chip.audio_pattern.unwrap()[..3], [0x80, 0x80, 0]);
+        chip.Vx[4] = 0x70;
+        exec(&mut chip, 0xF43A);
+        assert_eq!(chip.pitch, 0x70);
+        assert!(chip.audio_flag);
+    }
+
+    #[test]
+    fn last_word_of_xo_chip_memory() {
+        //PC wraps to 0 past 0xfffe, instructions that rewind it must wrap back
+        let mut chip = machine(Quirks::XO_CHIP);
+        chip.PC = 0xFFFE;
+        exec(&mut chip, 0x1200);
+        assert_eq!(chip.PC, 0x200);
+
+        chip.PC = 0xFFFE;
+        assert!(matches!(exec(&mut chip, 0x00FD), InstructionResult::BreakLoop));
+        assert_eq!(chip.PC, 0xFFFE);
+        let fault = try_exec(&mut chip, 0x00EE);
+        assert!(matches!(fault, Err(Chip8Error::StackUnderflow { address: 0xFFFE })));
+    }
+
+    #[test]
+    fn unknown_opcodes_fault_in_place() {
+        let mut chip = machine(Quirks::MODERN);
+        let fault = try_exec(&mut chip, 0x5121);
+        assert!(matches!(fault, Err(Chip8Error::InvalidOpcode { address: 0x200, opcode: 0x5121 })));
+        assert_eq!(chip.PC, 0x200);
+
+        // F000 nnnn only exists with XO-CHIP memory
+        let mut chip = machine(Quirks::SUPER_CHIP);
+        let fault = try_exec(&mut chip, 0xF000);
+        assert!(matches!(fault, Err(Chip8Error::InvalidOpcode { address: 0x200, opcode: 0xF000 })));
+        assert_eq!((chip.PC, chip.I), (0x200, 0));
+    }
+
+    #[test]
+    fn memory_faults() {
+        let mut chip = machine(Quirks::MODERN);
+        chip.I = 0xFFF;
+        let fault = try_exec(&mut chip, 0xF155);
+        assert!(matches!(fault, Err(Chip8Error::MemoryOutOfBounds { .. })));
+        assert_eq!(chip.PC, 0x200);
+
+        let rom = vec![0; 4096];
+        assert!(matches!(chip.load_rom(&rom), Err(Chip8Error::RomTooLarge { size: 4096, max: 3584 })));
+    }
+}
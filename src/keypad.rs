@@ -0,0 +1,75 @@
+use crate::frontend::InputEvent;
+
+/// pressed/released state of the 16 chip8 keys
+/// fed by KeyDown/KeyUp events, read by Ex9E, ExA1 and Fx0A
+#[derive(Copy, Clone, Default)]
+pub struct Keypad {
+    pub keys: [bool; 16],
+
+    //Last key that went from pressed to released, consumed by Fx0A
+    pub(crate) released: Option<u8>,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad { keys: [false; 16], released: None }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = true;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        let key = key & 0xF;
+        if self.keys[key as usize] {
+            self.keys[key as usize] = false;
+            self.released = Some(key);
+        }
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+
+    /// applies a KeyDown or KeyUp event, other events are ignored
+    pub fn handle_event(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::KeyDown(k) => self.press(k),
+            InputEvent::KeyUp(k) => self.release(k),
+            _ => {}
+        }
+    }
+
+    /// returns the key released since the last call, if any
+    pub fn take_released(&mut self) -> Option<u8> {
+        self.released.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keypad;
+
+    #[test]
+    fn keys_can_be_held_together() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x1);
+        keypad.press(0xA);
+        assert!(keypad.is_pressed(0x1));
+        assert!(keypad.is_pressed(0xA));
+        keypad.release(0x1);
+        assert!(!keypad.is_pressed(0x1));
+        assert!(keypad.is_pressed(0xA));
+    }
+
+    #[test]
+    fn release_is_reported_once() {
+        let mut keypad = Keypad::new();
+        keypad.release(0x5);
+        assert_eq!(keypad.take_released(), None);
+        keypad.press(0x5);
+        keypad.release(0x5);
+        assert_eq!(keypad.take_released(), Some(0x5));
+        assert_eq!(keypad.take_released(), None);
+    }
+}
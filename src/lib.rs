@@ -1,106 +1,198 @@
-use sdl2::pixels::Color;
 use std::env;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::audio::{ToneSettings, Waveform};
+use crate::constants::{CONFIG_FILE, DEFAULT_CLIP_SCALE, DEFAULT_INSTRUCTIONS_PER_FRAME, DEFAULT_PALETTE, DEFAULT_SCALE, DEFAULT_SCREENSHOT_SCALE};
+use crate::error::Chip8Error;
+use crate::frontend::Palette;
+use crate::headless::OutputFormat;
+use crate::movie::{Movie, MovieMode};
+use crate::quirks::Quirks;
 
 
+pub mod assembler;
+pub mod audio;
 pub mod chip8;
 pub mod instruction;
 pub mod constants;
-use crate::chip8::Chip8;
+pub mod disasm;
+pub mod error;
+pub mod frontend;
+pub mod headless;
+pub mod keypad;
+pub mod movie;
+pub mod palette;
+pub mod quirks;
+pub mod recorder;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod screenshot;
+#[cfg(feature = "sdl")]
+pub mod sdl;
 
 pub struct Config {
-    filename: String,
-    is_debug: bool
+    pub filename: String,
+    pub is_debug: bool,
+    pub instructions_per_frame: u32,
+    pub quirks: Quirks,
+
+    //Fixed seed for Cxkk, random on every run when None
+    pub seed: Option<u64>,
+
+    //Movie file to write the run to, or to play it back from
+    pub record: Option<String>,
+    pub play: Option<String>,
+
+    //Gif file or png directory to record the run to, and how many times 128 x 64 its frames are
+    pub clip: Option<String>,
+    pub clip_scale: u32,
+
+    //Wav file to record the buzzer to
+    pub wav: Option<String>,
+
+    //How the buzzer sounds, and whether it starts out muted
+    pub tone: ToneSettings,
+    pub mute: bool,
+
+    //Colours of the display, screenshots and clips
+    pub palette: Palette,
+
+    //How many times the display's size screenshots and headless pngs are
+    pub screenshot_scale: u32,
+
+    //Window size in multiples of 64 x 32, whether pixels are only scaled by whole numbers, and starting in fullscreen
+    pub scale: u32,
+    pub integer_scale: bool,
+    pub fullscreen: bool,
+
+    //Run without a window for a number of frames, then write the display in output format to output_file or stdout
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub output: OutputFormat,
+    pub output_file: Option<String>,
+
+    //Also write the final display of a headless run as a png
+    pub png_file: Option<String>,
+
+    //Keys to press during a headless run, see headless::parse_input_script
+    pub input_script: Option<String>,
 }
 
 impl Config {
     pub fn build(filename: &str, is_debug: bool ) -> Config {
         Config {
             filename: String::from(filename), 
-            is_debug: is_debug
+            is_debug,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            seed: None,
+            record: None,
+            play: None,
+            clip: None,
+            clip_scale: DEFAULT_CLIP_SCALE,
+            wav: None,
+            tone: ToneSettings::default(),
+            mute: false,
+            palette: DEFAULT_PALETTE,
+            screenshot_scale: DEFAULT_SCREENSHOT_SCALE,
+            scale: DEFAULT_SCALE,
+            integer_scale: false,
+            fullscreen: false,
+            headless: false,
+            frames: None,
+            output: OutputFormat::Ascii,
+            output_file: None,
+            png_file: None,
+            input_script: None,
         }
     }
-}
 
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
+    /// loads the movie to play, if any
+    /// a played movie brings its own quirks and speed, which replace the configured ones, and must match the rom
+    pub fn movie_mode(&mut self) -> Result<MovieMode, Chip8Error> {
+        match (&self.record, &self.play) {
+            (Some(_), Some(_)) => Err(Chip8Error::InvalidArgument(String::from("--record and --play can't be used together!"))),
+            (Some(path), None) => {
+                let seed = self.seed.unwrap_or_else(rand::random);
+                let rom_hash = movie::rom_hash(&chip8::read_rom(&self.filename)?);
+                Ok(MovieMode::Record { path: PathBuf::from(path), seed, rom_hash })
+            },
+            (None, Some(path)) => {
+                let movie = Movie::load(Path::new(path))?;
+                movie.check_rom(&chip8::read_rom(&self.filename)?)?;
+                self.quirks = movie.quirks;
+                self.instructions_per_frame = movie.instructions_per_frame;
+                Ok(MovieMode::Play(movie))
+            },
+            (None, None) => Ok(MovieMode::Off)
+        }
+    }
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
 
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+/// entry point of the command line
+/// `disasm <rom>` prints a listing, `asm <source> [out.ch8]` assembles a rom
+/// `run [flags] <rom>` or just `[flags] <rom>` runs the rom
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), Chip8Error> {
+    let mut args = args.peekable();
+    let program = args.next().unwrap_or_default();
+    match args.peek().map(|s| s.as_str()) {
+        Some("disasm") => {
+            args.next();
+            let filename = match args.next() {
+                Some(s) => s,
+                None => return Err(Chip8Error::InvalidArgument(String::from("Usage: disasm <rom>")))
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+            let rom = chip8::read_rom(&filename)?;
+            print!("{}", disasm::disassemble_rom(&rom)?);
+            Ok(())
+        },
+        Some("asm") => {
+            args.next();
+            let source = match args.next() {
+                Some(s) => s,
+                None => return Err(Chip8Error::InvalidArgument(String::from("Usage: asm <source> [out.ch8]")))
+            };
+            let out = args.next().unwrap_or_else(|| Path::new(&source).with_extension("ch8").display().to_string());
+            let rom = assembler::assemble_file(Path::new(&source))?;
+            fs::write(&out, &rom)?;
+            println!("Wrote {} bytes to {}", rom.len(), out);
+            Ok(())
+        },
+        Some("run") => {
+            args.next();
+            open_window(std::iter::once(program).chain(args))
+        },
+        _ => open_window(std::iter::once(program).chain(args))
     }
 }
 
+/// parse the arguments and run the rom in an sdl2 window, or without one for --headless
+pub fn open_window(args: impl Iterator<Item = String>) -> Result<(), Chip8Error> {
+    let cfg = handle_args(args)?;
+    if cfg.headless {
+        return headless::run_headless(cfg);
+    }
 
-/// open sdl2 window and create a new chip8 display
-/// draw display to screen in a loop
-/// display might onl be updated when necessary instead of 60 FPS for better optimization
-pub fn open_window(args: impl Iterator<Item = String>) -> Result<(), &'static str> {
-    let cfg = match handle_args(args) {
-        Ok(c) => c,
-        Err(e) => return Err(e)
-    };
-
-    let Config {filename, is_debug} = cfg;
-
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),  // mono
-        samples: None       // default sample size
-    };
-
-    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25
-        }
-    }).unwrap();
-
-    let window = video_subsystem.window("Chip-8 emulator", 640, 320)
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    let event_pump = sdl_context.event_pump().unwrap();
+    #[cfg(feature = "sdl")]
+    return sdl::open_window(cfg);
 
-    let mut my_chip8 = Chip8::new_default(canvas);
-    match my_chip8.start_device(&filename, is_debug, event_pump, device) {
-        Err(e) => println!("Error: {}", e),
-        Ok(()) => {}
-    };
-
-    Ok(())
+    #[cfg(not(feature = "sdl"))]
+    {
+        let _ = cfg;
+        Err(Chip8Error::Frontend(String::from("this build has no SDL2 support")))
+    }
 }
 
-pub fn handle_args(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-    args.next();
-    let filename = match args.next() {
-        Some(s) => s,
-        None => return Err("No filename provided!")
-    };
+/// parses the command line, after the settings of the config file: --config <file>, or chip8.cfg if there is one
+pub fn handle_args(args: impl Iterator<Item = String>) -> Result<Config, Chip8Error> {
+    parse_options(with_config_file(args.skip(1).collect())?)
+}
 
+/// parses options without the program name, no config file is looked at
+pub fn parse_options(args: Vec<String>) -> Result<Config, Chip8Error> {
+    let mut args = args.into_iter();
     let is_debug = match env::var("CH8_DEBUG") {
         Ok(val) => {
             val == "1"
@@ -109,32 +201,228 @@ pub fn handle_args(mut args: impl Iterator<Item = String>) -> Result<Config, &'s
             false
         }
     };
+    let mut cfg = Config::build("", is_debug);
+    let mut filename = None;
+    let (mut foreground, mut background) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ipf" => {
+                cfg.instructions_per_frame = match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--ipf needs a positive number of instructions per frame!")))
+                };
+            },
+            "--quirks" => {
+                cfg.quirks = match args.next().and_then(|name| Quirks::from_name(&name)) {
+                    Some(q) => q,
+                    None => return Err(Chip8Error::InvalidArgument(format!("--quirks needs one of: {}", Quirks::NAMES.join(", "))))
+                };
+            },
+            "--seed" => {
+                cfg.seed = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => Some(n),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--seed needs a number!")))
+                };
+            },
+            "--record" => {
+                cfg.record = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--record needs a movie file to write!")))
+                };
+            },
+            "--play" => {
+                cfg.play = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--play needs a movie file to read!")))
+                };
+            },
+            "--clip" => {
+                cfg.clip = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--clip needs a .gif file or a directory for png frames!")))
+                };
+            },
+            "--clip-scale" => {
+                cfg.clip_scale = match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--clip-scale needs a positive number!")))
+                };
+            },
+            "--wav" => {
+                cfg.wav = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--wav needs a file to write the audio to!")))
+                };
+            },
+            "--frequency" => {
+                cfg.tone.frequency = match args.next().and_then(|n| n.parse().ok()).filter(|&n: &f32| n > 0.0) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--frequency needs a frequency in Hz!")))
+                };
+            },
+            "--waveform" => {
+                cfg.tone.waveform = match args.next().and_then(|name| Waveform::from_name(&name)) {
+                    Some(waveform) => waveform,
+                    None => return Err(Chip8Error::InvalidArgument(format!("--waveform needs one of: {}", Waveform::NAMES.join(", "))))
+                };
+            },
+            "--volume" => {
+                cfg.tone.volume = match args.next().and_then(|n| n.parse().ok()).filter(|n: &f32| (0.0..=1.0).contains(n)) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--volume needs a number from 0 to 1!")))
+                };
+            },
+            "--mute" => cfg.mute = true,
+            "--scale" => {
+                cfg.scale = match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--scale needs a positive number!")))
+                };
+            },
+            "--screenshot-scale" => {
+                cfg.screenshot_scale = match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(n) => n,
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--screenshot-scale needs a positive number!")))
+                };
+            },
+            "--integer-scale" => cfg.integer_scale = true,
+            "--fullscreen" => cfg.fullscreen = true,
+            "--palette" => {
+                cfg.palette = match args.next().and_then(|name| palette::from_name(&name)) {
+                    Some(p) => p,
+                    None => return Err(Chip8Error::InvalidArgument(format!("--palette needs one of: {}", palette::names().join(", "))))
+                };
+            },
+            "--foreground" => {
+                foreground = match args.next().and_then(|c| palette::parse_color(&c)) {
+                    Some(c) => Some(c),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--foreground needs a colour like ff8000!")))
+                };
+            },
+            "--background" => {
+                background = match args.next().and_then(|c| palette::parse_color(&c)) {
+                    Some(c) => Some(c),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--background needs a colour like 101010!")))
+                };
+            },
+            "--headless" => cfg.headless = true,
+            "--frames" => {
+                cfg.frames = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => Some(n),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--frames needs a number of frames!")))
+                };
+            },
+            "--output" => {
+                cfg.output = match args.next().and_then(|name| OutputFormat::from_name(&name)) {
+                    Some(format) => format,
+                    None => return Err(Chip8Error::InvalidArgument(format!("--output needs one of: {}", OutputFormat::NAMES.join(", "))))
+                };
+            },
+            "--out" => {
+                cfg.output_file = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--out needs a file to write the display to!")))
+                };
+            },
+            "--png" => {
+                cfg.png_file = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--png needs a file to write the screenshot to!")))
+                };
+            },
+            "--input" => {
+                cfg.input_script = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(Chip8Error::InvalidArgument(String::from("--input needs an input script!")))
+                };
+            },
+            _ if arg.starts_with("--") => return Err(Chip8Error::InvalidArgument(format!("Unknown option {}!", arg))),
+            _ => filename = Some(arg)
+        }
+    }
+    cfg.filename = match filename {
+        Some(s) => s,
+        None => return Err(Chip8Error::InvalidArgument(String::from("No filename provided!")))
+    };
 
-    Ok(Config::build(&filename, is_debug))
+    //the colours replace the first plane and the background of the palette, whatever order they came in
+    if let Some(color) = foreground {
+        cfg.palette[1] = color;
+    }
+    if let Some(color) = background {
+        cfg.palette[0] = color;
+    }
+    Ok(cfg)
 }
 
 
 
 
 
+/// puts the options of the config file in front of args, so the command line overrides them
+/// --config <file> is taken out of args, without it chip8.cfg is read if it exists
+fn with_config_file(mut args: Vec<String>) -> Result<Vec<String>, Chip8Error> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(Chip8Error::InvalidArgument(String::from("--config needs a config file!")));
+            }
+            args.remove(i);
+            PathBuf::from(args.remove(i))
+        },
+        None if Path::new(CONFIG_FILE).exists() => PathBuf::from(CONFIG_FILE),
+        None => return Ok(args)
+    };
+    let mut options = config_file_args(&fs::read_to_string(&path)?)?;
+    options.extend(args);
+    Ok(options)
+}
+
+/// turns the lines of a config file into command line options, lines starting with # are comments
+/// `key = value` is `--key value`, `key` or `key = true` is `--key` and `key = false` is left out
+pub fn config_file_args(text: &str) -> Result<Vec<String>, Chip8Error> {
+    let mut args = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (line, "true")
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) || key == "config" {
+            return Err(Chip8Error::InvalidArgument(format!("config file line {}: expected `key = value`, got `{}`", number + 1, line)));
+        }
+        match value {
+            "true" => args.push(format!("--{}", key)),
+            "false" => {},
+            _ => args.extend([format!("--{}", key), String::from(value)])
+        }
+    }
+    Ok(args)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::chip8::Chip8;
-    use sdl2::pixels::Color;
-
-    fn get_test_device() -> Chip8 {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    #[test]
+    fn config_file_settings_are_overridden_by_the_command_line() {
+        use crate::audio::Waveform;
+        use crate::{config_file_args, parse_options};
 
-        let window = video_subsystem.window("Chip-8 emulator", 640, 320)
-        .position_centered()
-        .build()
-        .unwrap();
+        let text = "# buzzer\nwaveform = sine\nvolume = 0.5\nmute\nheadless = false\n";
+        let mut args = config_file_args(text).unwrap();
+        assert_eq!(args, ["--waveform", "sine", "--volume", "0.5", "--mute"]);
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        args.extend(["--volume", "0.1", "maze.ch8"].map(String::from));
+        let cfg = parse_options(args).unwrap();
+        assert_eq!((cfg.tone.waveform, cfg.tone.volume, cfg.mute, cfg.headless), (Waveform::Sine, 0.1, true, false));
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        Chip8::new_default(canvas)
+        assert!(config_file_args("two words = 1").is_err());
+        assert!(parse_options(["--volume", "2", "maze.ch8"].map(String::from).to_vec()).is_err());
+        assert!(parse_options(["--bogus", "maze.ch8"].map(String::from).to_vec()).is_err());
+        assert!(parse_options(["--ipf", "0", "maze.ch8"].map(String::from).to_vec()).is_err());
+        assert!(parse_options(["--screenshot-scale", "0", "maze.ch8"].map(String::from).to_vec()).is_err());
+        assert_eq!(parse_options(["--screenshot-scale", "3", "maze.ch8"].map(String::from).to_vec()).unwrap().screenshot_scale, 3);
     }
 }
@@ -1,8 +1,8 @@
-use chip_8_emulator::open_window;
+use chip_8_emulator::run;
 fn main() {
     let args = std::env::args();
-    match open_window(args) {
-        Err(e) => println!("Error: {}", e),
-        _ => {}
-    };
-}
\ No newline at end of file
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{fs, path::Path, path::PathBuf};
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::frontend::InputEvent;
+use crate::headless::fnv1a;
+use crate::instruction::InstructionResult;
+use crate::quirks::Quirks;
+use crate::savestate::Reader;
+
+/// first bytes of every movie file
+const MAGIC: &[u8; 4] = b"CH8M";
+
+/// movies of other versions can't be played, version 1 had no rom hash and version 2 one byte of quirks
+pub const MOVIE_VERSION: u8 = 3;
+
+/// every keypad change of a run with the frame it happened in
+/// together with the rom, seed, quirks and speed the run is reproduced bit for bit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    //rom_hash of the rom the run was recorded with, playback refuses any other
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+
+    //Length of the run in frames, playback hands control back after it
+    pub frames: u64,
+
+    //KeyDown/KeyUp events in the order they were applied, frame numbers count from 0
+    pub events: Vec<(u64, InputEvent)>,
+}
+
+/// what the run loop does with movies
+pub enum MovieMode {
+    Off,
+    //Record with the seed and write the movie to the path when the run ends
+    Record { path: PathBuf, seed: u64, rom_hash: u64 },
+    //Seed from the movie and feed its keys instead of the live ones
+    Play(Movie),
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, quirks: Quirks, seed: u64, instructions_per_frame: u32) -> Movie {
+        Movie { rom_hash, quirks, seed, instructions_per_frame, frames: 0, events: Vec::new() }
+    }
+
+    /// fails unless rom is the one the movie was recorded with
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let hash = rom_hash(rom);
+        if hash != self.rom_hash {
+            return Err(invalid(format!("recorded with the rom {:016x}, this one is {:016x}", self.rom_hash, hash)));
+        }
+        Ok(())
+    }
+
+    /// keeps event if it changes the keypad, anything else is ignored
+    pub fn record(&mut self, frame: u64, event: InputEvent) {
+        if let InputEvent::KeyDown(_) | InputEvent::KeyUp(_) = event {
+            self.events.push((frame, event));
+        }
+        self.frames = self.frames.max(frame + 1);
+    }
+
+    /// the events recorded for frame
+    pub fn events_at(&self, frame: u64) -> impl Iterator<Item = InputEvent> + '_ {
+        let start = self.events.partition_point(|&(f, _)| f < frame);
+        self.events[start..].iter().take_while(move |&&(f, _)| f == frame).map(|&(_, event)| event)
+    }
+
+    /// plays the whole movie on chip without a frontend, chip must have the rom loaded and PC set
+    /// runs the same frames as the run loop: halted machines only tick their timers
+    pub fn replay(&self, chip: &mut Chip8) -> Result<(), Chip8Error> {
+        self.replay_with(chip, |_| Ok(()))
+    }
+
+    /// replay, calling after_frame with the machine at the end of every frame
+    pub fn replay_with(&self, chip: &mut Chip8, mut after_frame: impl FnMut(&Chip8) -> Result<(), Chip8Error>) -> Result<(), Chip8Error> {
+        chip.seed(self.seed);
+        let mut halted = false;
+        for frame in 0..self.frames {
+            for event in self.events_at(frame) {
+                chip.keypad.handle_event(event);
+            }
+            if halted {
+                chip.tick_timers();
+            } else if let InstructionResult::BreakLoop = chip.run_frame(self.instructions_per_frame)? {
+                halted = true;
+            }
+            after_frame(chip)?;
+        }
+        Ok(())
+    }
+
+    /// layout, multi-byte values big-endian:
+    /// magic, version, rom hash (u64), quirk flags (u16), instructions per frame (u32), seed (u64), frames (u64),
+    /// event count (u32), then per event its frame (u64) and key, with bit 7 set for KeyDown
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(MOVIE_VERSION);
+        out.extend_from_slice(&self.rom_hash.to_be_bytes());
+        out.extend_from_slice(&self.quirks.to_bits().to_be_bytes());
+        out.extend_from_slice(&self.instructions_per_frame.to_be_bytes());
+        out.extend_from_slice(&self.seed.to_be_bytes());
+        out.extend_from_slice(&self.frames.to_be_bytes());
+        out.extend_from_slice(&(self.events.len() as u32).to_be_bytes());
+        for &(frame, event) in &self.events {
+            out.extend_from_slice(&frame.to_be_bytes());
+            out.push(match event {
+                InputEvent::KeyDown(key) => 0x80 | key,
+                InputEvent::KeyUp(key) => key,
+                _ => unreachable!("only key events are recorded")
+            });
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, Chip8Error> {
+        let mut reader = Reader::new(bytes, Chip8Error::InvalidMovie);
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a movie file"));
+        }
+        let version = reader.byte()?;
+        if version != MOVIE_VERSION {
+            return Err(invalid(format!("movie version {} is not supported, expected {}", version, MOVIE_VERSION)));
+        }
+        let rom_hash = reader.u64()?;
+        let quirks = Quirks::from_bits(reader.u16()?);
+        let instructions_per_frame = reader.u32()?;
+        let seed = reader.u64()?;
+        let frames = reader.u64()?;
+        let mut movie = Movie { rom_hash, quirks, seed, instructions_per_frame, frames, events: Vec::new() };
+
+        let count = reader.u32()?;
+        for _ in 0..count {
+            let frame = reader.u64()?;
+            if frame >= frames || movie.events.last().is_some_and(|&(last, _)| frame < last) {
+                return Err(invalid(format!("event at frame {} is out of order", frame)));
+            }
+            let key = reader.byte()?;
+            let event = if key & 0x80 != 0 { InputEvent::KeyDown(key & 0xF) } else { InputEvent::KeyUp(key & 0xF) };
+            movie.events.push((frame, event));
+        }
+        if !reader.at_end() {
+            return Err(invalid("trailing bytes after the movie"));
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Chip8Error> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, Chip8Error> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// 64-bit FNV-1a of the rom bytes, how a movie recognises its rom
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom.iter().copied())
+}
+
+fn invalid(message: impl Into<String>) -> Chip8Error {
+    Chip8Error::InvalidMovie(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rom_hash, Movie};
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::frontend::InputEvent;
+    use crate::quirks::Quirks;
+
+    // loop: RND V0, 0xFF; SKNP V1 (key 0); ADD V2, 1; DRW V0, V0, 1; JP loop
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xE1, 0xA1, 0x72, 0x01, 0xD0, 0x01, 0x12, 0x00];
+
+    fn boot() -> Chip8 {
+        let mut chip = Chip8::new(Quirks::COSMAC_VIP);
+        chip.load_rom(&ROM).unwrap();
+        chip.PC = 0x200;
+        chip
+    }
+
+    #[test]
+    fn playback_reproduces_the_run() {
+        let mut movie = Movie::new(rom_hash(&ROM), Quirks::COSMAC_VIP, 0xDEADBEEF, 9);
+        movie.record(3, InputEvent::KeyDown(0));
+        movie.record(3, InputEvent::Step);
+        movie.record(20, InputEvent::KeyUp(0));
+        movie.record(39, InputEvent::KeyDown(5));
+        assert_eq!(movie.events.len(), 3);
+        assert_eq!(movie.frames, 40);
+
+        let restored = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(restored, movie);
+
+        let (mut first, mut second) = (boot(), boot());
+        movie.replay(&mut first).unwrap();
+        restored.replay(&mut second).unwrap();
+        assert_eq!((first.Vx, first.PC, first.I), (second.Vx, second.PC, second.I));
+        // display_wait ends every frame at DRW, so key 0 held for 17 frames adds 17
+        assert_eq!(first.Vx[2], 17);
+        let pixels = |chip: &Chip8| (0..32).flat_map(|y| (0..64).map(move |x| (x, y))).map(|(x, y)| chip.display.color(x, y)).collect::<Vec<_>>();
+        assert_eq!(pixels(&first), pixels(&second));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let mut bytes = Movie::new(rom_hash(&ROM), Quirks::default(), 1, 10).to_bytes();
+        assert!(Movie::from_bytes(&bytes[..10]).is_err());
+        bytes[4] = 0;
+        assert!(Movie::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn only_plays_with_its_rom() {
+        let movie = Movie::new(rom_hash(&ROM), Quirks::default(), 1, 10);
+        assert!(movie.check_rom(&ROM).is_ok());
+        let mut modified = ROM;
+        modified[9] = 0x02;
+        assert!(matches!(movie.check_rom(&modified), Err(Chip8Error::InvalidMovie(_))));
+    }
+}
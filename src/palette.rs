@@ -0,0 +1,73 @@
+use crate::constants::DEFAULT_PALETTE;
+use crate::frontend::Palette;
+
+/// palettes that can be picked by name and are cycled through by the hotkey
+/// colours are background, first plane, second plane and both planes
+pub const PALETTES: [(&str, Palette); 5] = [
+    ("classic", DEFAULT_PALETTE),
+    ("amber", [[20, 12, 0], [255, 176, 0], [170, 100, 0], [255, 230, 160]]),
+    ("white", [[0, 0, 0], [255, 255, 255], [120, 120, 120], [190, 190, 190]]),
+    ("lcd", [[155, 188, 15], [15, 56, 15], [48, 98, 48], [90, 130, 30]]),
+    ("contrast", [[0, 0, 0], [255, 255, 0], [0, 255, 255], [255, 255, 255]]),
+];
+
+pub fn from_name(name: &str) -> Option<Palette> {
+    PALETTES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, palette)| palette)
+}
+
+/// the names of PALETTES, in cycling order
+pub fn names() -> Vec<&'static str> {
+    PALETTES.iter().map(|&(name, _)| name).collect()
+}
+
+/// name of palette if it is one of PALETTES
+pub fn name_of(palette: &Palette) -> Option<&'static str> {
+    PALETTES.iter().find(|(_, p)| p == palette).map(|&(name, _)| name)
+}
+
+/// the palette after palette when cycling from start, wrapping around
+/// a start that has no name takes its turn after the last named palette, so custom colours aren't lost
+pub fn next(palette: &Palette, start: &Palette) -> Palette {
+    match PALETTES.iter().position(|(_, p)| p == palette) {
+        Some(i) if i + 1 < PALETTES.len() => PALETTES[i + 1].1,
+        Some(_) if name_of(start).is_none() => *start,
+        _ => PALETTES[0].1
+    }
+}
+
+/// a colour written as rrggbb in hex, with or without a leading #
+pub fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_name, name_of, next, parse_color, PALETTES};
+
+    #[test]
+    fn palettes_by_name_and_in_turn() {
+        let amber = from_name("Amber").unwrap();
+        assert_eq!(name_of(&amber), Some("amber"));
+        assert_eq!(name_of(&next(&amber, &amber)), Some("white"));
+        assert_eq!(next(&PALETTES[4].1, &amber), PALETTES[0].1);
+
+        //custom colours come back after the last named palette
+        let custom = [[1, 2, 3]; 4];
+        assert_eq!(name_of(&custom), None);
+        assert_eq!(next(&custom, &custom), PALETTES[0].1);
+        assert_eq!(next(&PALETTES[4].1, &custom), custom);
+    }
+
+    #[test]
+    fn colours_are_hex_triplets() {
+        assert_eq!(parse_color("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_color("0A0b0C"), Some([10, 11, 12]));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("gg0000"), None);
+    }
+}
@@ -0,0 +1,181 @@
+/// behaviour of the opcodes that differ between chip8 interpreters
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    //8xy6/8xyE shift Vy and store the result in Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    //How far Fx55/Fx65 move I
+    pub load_store_index: IndexIncrement,
+
+    //Bnnn is read as Bxnn and jumps to xnn + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+
+    //8xy1/8xy2/8xy3 reset VF to 0
+    pub vf_reset: bool,
+
+    //the parts of a sprite past the screen edges are cut off instead of wrapping around, the origin always wraps
+    pub clip_sprites: bool,
+
+    //Dxyn waits for the start of the next frame before execution continues
+    pub display_wait: bool,
+
+    //XO-CHIP 64KB address space instead of 4KB
+    pub extended_memory: bool,
+
+    //Dxy0 draws a 16x16 sprite, without it Dxy0 draws nothing like on the VIP
+    pub big_sprites: bool,
+}
+
+/// what Fx55/Fx65 do to I after touching V0 through Vx
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    //I stays where it was
+    Unchanged,
+    //I ends up on the last register touched, CHIP-48 is off by one
+    X,
+    //I ends up past the last register touched, like on the VIP
+    XPlusOne,
+}
+
+impl IndexIncrement {
+    /// how much I grows after Fx55/Fx65 with x
+    pub fn amount(&self, x: u8) -> u16 {
+        match self {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => x as u16,
+            IndexIncrement::XPlusOne => x as u16 + 1,
+        }
+    }
+}
+
+impl Quirks {
+    /// the original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+        extended_memory: false,
+        big_sprites: false,
+    };
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::X,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+        big_sprites: false,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+        big_sprites: true,
+    };
+
+    /// what most modern interpreters do: shifts in place, I untouched by Fx55/Fx65, sprites wrap
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::Unchanged,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        extended_memory: false,
+        big_sprites: true,
+    };
+
+    /// XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+        extended_memory: true,
+        big_sprites: true,
+    };
+
+    /// one name per preset, from_name also takes a few aliases
+    pub const NAMES: [&'static str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+    /// packs the quirks into two bytes for save states and movies, in field order from bit 0
+    /// bit 1 is set when Fx55/Fx65 move I at all, bit 7 when they move it by x only and bit 8 is big_sprites
+    pub fn to_bits(&self) -> u16 {
+        [
+            self.shift_uses_vy,
+            self.load_store_index != IndexIncrement::Unchanged,
+            self.jump_uses_vx,
+            self.vf_reset,
+            self.clip_sprites,
+            self.display_wait,
+            self.extended_memory,
+            self.load_store_index == IndexIncrement::X,
+            self.big_sprites,
+        ].iter().enumerate().fold(0, |bits, (i, &on)| bits | (on as u16) << i)
+    }
+
+    /// the inverse of to_bits
+    pub fn from_bits(bits: u16) -> Quirks {
+        let on = |i: u8| bits & (1 << i) != 0;
+        Quirks {
+            shift_uses_vy: on(0),
+            load_store_index: match (on(1), on(7)) {
+                (false, _) => IndexIncrement::Unchanged,
+                (true, false) => IndexIncrement::XPlusOne,
+                (true, true) => IndexIncrement::X,
+            },
+            jump_uses_vx: on(2),
+            vf_reset: on(3),
+            clip_sprites: on(4),
+            display_wait: on(5),
+            extended_memory: on(6),
+            big_sprites: on(8),
+        }
+    }
+
+    /// looks up a preset by name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quirks;
+
+    #[test]
+    fn presets_survive_packing() {
+        for name in Quirks::NAMES {
+            let quirks = Quirks::from_name(name).unwrap();
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks, "{}", name);
+        }
+        assert_ne!(Quirks::CHIP_48, Quirks::SUPER_CHIP);
+    }
+}
@@ -0,0 +1,92 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// where Cxkk gets its random bytes from
+/// boxed_clone lets a cloned Chip8, e.g. a rewind snapshot, continue the same sequence
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+    fn boxed_clone(&self) -> Box<dyn RandomSource>;
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Self {
+        self.boxed_clone()
+    }
+}
+
+/// pseudo random bytes, the same seed always gives the same sequence
+#[derive(Clone)]
+pub struct SeededRandom {
+    rng: StdRng,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// seeded from the operating system, different on every run
+    pub fn from_entropy() -> SeededRandom {
+        SeededRandom { rng: StdRng::from_entropy() }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.rng.gen()
+    }
+
+    fn boxed_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+/// hands out the given bytes in order and starts over at the end, for tests
+#[derive(Clone)]
+pub struct ScriptedRandom {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl ScriptedRandom {
+    /// bytes must not be empty
+    pub fn new(bytes: &[u8]) -> ScriptedRandom {
+        assert!(!bytes.is_empty(), "a scripted random source needs at least one byte");
+        ScriptedRandom { bytes: bytes.to_vec(), position: 0 }
+    }
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.position];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn boxed_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RandomSource, ScriptedRandom, SeededRandom};
+
+    #[test]
+    fn same_seed_same_bytes() {
+        let mut a = SeededRandom::new(42);
+        let mut b = SeededRandom::new(42);
+        let first: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert_eq!(first, (0..32).map(|_| b.next_byte()).collect::<Vec<u8>>());
+
+        //a clone carries on from where the original is
+        let mut c = a.boxed_clone();
+        assert_eq!(a.next_byte(), c.next_byte());
+    }
+
+    #[test]
+    fn scripted_bytes_repeat() {
+        let mut script = ScriptedRandom::new(&[1, 2, 3]);
+        let bytes: Vec<u8> = (0..5).map(|_| script.next_byte()).collect();
+        assert_eq!(bytes, vec![1, 2, 3, 1, 2]);
+    }
+}
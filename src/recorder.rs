@@ -0,0 +1,195 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::audio::ToneSettings;
+use crate::chip8::Chip8Display;
+use crate::constants::{HIRES_HEIGHT, HIRES_WIDTH};
+use crate::error::Chip8Error;
+use crate::frontend::Palette;
+use crate::screenshot::{encode_png, image_error, indexed_pixels};
+
+/// gif delays are in hundredths of a second, browsers slow down anything shorter than this
+const MIN_GIF_DELAY: u64 = 2;
+
+/// where a recorder puts its frames
+enum Output {
+    //One animated gif, a frame is only written once the next different one arrives so identical frames are merged
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        pending: Option<Vec<u8>>,
+        //Hundredths of a second written so far, delays are rounded against this so the clip keeps the 60Hz clock
+        written: u64,
+    },
+    //A directory with one numbered png per frame
+    Png(PathBuf),
+}
+
+/// captures the display once per 60Hz frame into an animated gif or a png sequence
+/// frames are 128 x 64 times scale so no high resolution pixel is lost, low resolution pixels are twice that size
+pub struct Recorder {
+    output: Output,
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    palette: Palette,
+    frames: u64,
+}
+
+/// clips of a windowed run: path is recorded from the first frame on,
+/// the hotkey starts and stops further clips at scale
+/// the buzzer of the whole run goes to the wav file at audio, frame for frame with the clips, sounding like tone
+/// screenshots taken with the hotkey are screenshot_scale times the display
+pub struct ClipOptions {
+    pub path: Option<PathBuf>,
+    pub scale: u32,
+    pub audio: Option<PathBuf>,
+    pub tone: ToneSettings,
+    pub screenshot_scale: u32,
+}
+
+impl Recorder {
+    /// paths ending in .gif get an animated gif, anything else is a directory for frame-00000.png, frame-00001.png, ...
+    pub fn start(path: &Path, scale: u32, palette: &Palette) -> Result<Recorder, Chip8Error> {
+        let scale = scale.max(1) as usize;
+        let (width, height) = (HIRES_WIDTH * scale, HIRES_HEIGHT * scale);
+        let is_gif = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+        let output = if is_gif {
+            //gif sizes are 16 bit, checked before the file is created
+            let too_large = |_| Chip8Error::InvalidArgument(format!("clip scale {} is too large for a gif, 511 is the most", scale));
+            let (gif_width, gif_height) = (u16::try_from(width).map_err(too_large)?, u16::try_from(height).map_err(too_large)?);
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, gif_width, gif_height, &palette.concat()).map_err(image_error)?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(image_error)?;
+            Output::Gif { encoder, pending: None, written: 0 }
+        } else {
+            fs::create_dir_all(path)?;
+            Output::Png(path.to_path_buf())
+        };
+        Ok(Recorder { output, path: path.to_path_buf(), width, height, palette: *palette, frames: 0 })
+    }
+
+    /// adds one frame showing display
+    pub fn capture(&mut self, display: &Chip8Display) -> Result<(), Chip8Error> {
+        let pixels = indexed_pixels(display, self.width, self.height);
+        match &mut self.output {
+            Output::Gif { encoder, pending, written } => {
+                if pending.as_ref() != Some(&pixels) {
+                    let delay = centiseconds(self.frames) - *written;
+                    if let Some(previous) = pending.take() {
+                        if delay >= MIN_GIF_DELAY {
+                            write_gif_frame(encoder, &previous, self.width, self.height, delay)?;
+                            *written += delay;
+                        }
+                        //a frame too short for a gif is dropped, the next one takes its time
+                    }
+                    *pending = Some(pixels);
+                }
+            },
+            Output::Png(dir) => {
+                let png = encode_png(&pixels, self.width, self.height, &self.palette)?;
+                fs::write(dir.join(format!("frame-{:05}.png", self.frames)), png)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// writes what is still pending and closes the clip, returns the number of frames
+    pub fn finish(self) -> Result<u64, Chip8Error> {
+        if let Output::Gif { mut encoder, pending, written } = self.output {
+            if let Some(last) = pending {
+                let delay = (centiseconds(self.frames) - written).max(MIN_GIF_DELAY);
+                write_gif_frame(&mut encoder, &last, self.width, self.height, delay)?;
+            }
+            encoder.into_inner()?;
+        }
+        Ok(self.frames)
+    }
+}
+
+/// time at the start of frame, in hundredths of a second
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + 30) / 60
+}
+
+fn write_gif_frame(encoder: &mut gif::Encoder<BufWriter<File>>, pixels: &[u8], width: usize, height: usize, delay: u64) -> Result<(), Chip8Error> {
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        delay: delay.min(u16::MAX as u64) as u16,
+        buffer: Cow::Borrowed(pixels),
+        ..gif::Frame::default()
+    };
+    encoder.write_frame(&frame).map_err(image_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recorder;
+    use crate::chip8::Chip8;
+    use crate::constants::DEFAULT_PALETTE;
+    use std::fs::{self, File};
+
+    #[test]
+    fn gif_merges_still_frames_and_keeps_time() {
+        let path = std::env::temp_dir().join(format!("chip8-clip-{}.gif", std::process::id()));
+        let mut chip = Chip8::new_default();
+        let mut recorder = Recorder::start(&path, 1, &DEFAULT_PALETTE).unwrap();
+        //one second still, then one second with a sprite
+        for frame in 0..120 {
+            if frame == 60 {
+                chip.draw_sprite_in_mem_to_x_y(0x50, 0, 0, 5).unwrap();
+            }
+            recorder.capture(&chip.display).unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), 120);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, vec![100, 100]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn gif_scale_has_to_fit_16_bits() {
+        let path = std::env::temp_dir().join(format!("chip8-huge-clip-{}.gif", std::process::id()));
+        assert!(Recorder::start(&path, 512, &DEFAULT_PALETTE).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn png_sequence_has_a_file_per_frame() {
+        let dir = std::env::temp_dir().join(format!("chip8-frames-{}", std::process::id()));
+        let mut chip = Chip8::new_default();
+        //a single high resolution pixel survives scale 1
+        chip.display.set_hires(true);
+        chip.memory[0x300] = 0x40;
+        chip.draw_sprite_in_mem_to_x_y(0x300, 0, 0, 1).unwrap();
+        let mut recorder = Recorder::start(&dir, 1, &DEFAULT_PALETTE).unwrap();
+        for _ in 0..3 {
+            recorder.capture(&chip.display).unwrap();
+        }
+        recorder.finish().unwrap();
+        assert!(dir.join("frame-00002.png").exists());
+        assert!(!dir.join("frame-00003.png").exists());
+
+        let mut reader = png::Decoder::new(File::open(dir.join("frame-00000.png")).unwrap()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((frame.width, frame.height), (128, 64));
+        assert_eq!(pixels[..3], [0, 1, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
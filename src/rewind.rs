@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::chip8::Chip8;
+use crate::frontend::InputEvent;
+
+/// a machine at the start of a frame and the key events of every frame run since
+struct Snapshot {
+    chip: Chip8,
+    inputs: Vec<Vec<InputEvent>>,
+}
+
+/// ring buffer of periodic snapshots for running a game backwards
+/// going back one frame restores the snapshot before it and re-simulates forward with
+/// the recorded keys, so every rewound frame is exactly the frame that was shown
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+
+    //How many bytes the snapshots may take up and how many frames lie between two of them
+    budget: usize,
+    interval: usize,
+    instructions_per_frame: u32,
+}
+
+impl Rewind {
+    pub fn new(budget: usize, interval: usize, instructions_per_frame: u32) -> Rewind {
+        Rewind { snapshots: VecDeque::new(), budget, interval: interval.max(1), instructions_per_frame }
+    }
+
+    /// called at the start of every frame, before the events are applied and the frame is run
+    /// only key events are kept, everything else has no effect on the machine
+    pub fn record(&mut self, chip: &Chip8, events: &[InputEvent]) {
+        let due = self.snapshots.back().is_none_or(|s| s.inputs.len() >= self.interval);
+        if due {
+            let capacity = (self.budget / snapshot_size(chip)).max(1);
+            while self.snapshots.len() >= capacity {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(Snapshot { chip: chip.clone(), inputs: Vec::new() });
+        }
+        let keys = events.iter().copied().filter(|e| matches!(e, InputEvent::KeyDown(_) | InputEvent::KeyUp(_))).collect();
+        if let Some(newest) = self.snapshots.back_mut() {
+            newest.inputs.push(keys);
+        }
+    }
+
+    /// the machine as it was at the start of the last recorded frame, which is forgotten
+    /// None once the buffer is used up
+    pub fn step_back(&mut self) -> Option<Chip8> {
+        let newest = self.snapshots.back_mut()?;
+        newest.inputs.pop();
+        let mut chip = newest.chip.clone();
+        for events in &newest.inputs {
+            for event in events {
+                chip.keypad.handle_event(*event);
+            }
+            if chip.run_frame(self.instructions_per_frame).is_err() {
+                break;
+            }
+        }
+        if newest.inputs.is_empty() {
+            //the snapshot itself is the frame, the next step starts from the one before
+            self.snapshots.pop_back();
+        }
+        Some(chip)
+    }
+
+    /// number of frames that can still be stepped back
+    pub fn frames(&self) -> usize {
+        self.snapshots.iter().map(|s| s.inputs.len()).sum()
+    }
+
+    /// forgets everything, used when the machine is replaced
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+/// rough number of bytes a snapshot of chip takes up
+fn snapshot_size(chip: &Chip8) -> usize {
+    mem::size_of::<Snapshot>() + chip.memory.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewind;
+    use crate::chip8::Chip8;
+    use crate::frontend::InputEvent;
+
+    #[test]
+    fn steps_back_to_the_exact_frames() {
+        // loop: RND V0, 0xFF; SKNP V1 (key 0); ADD V2, 1; DRW V0, V0, 1; JP loop
+        let mut chip = Chip8::new_default();
+        chip.load_rom(&[0xC0, 0xFF, 0xE1, 0xA1, 0x72, 0x01, 0xD0, 0x01, 0x12, 0x00]).unwrap();
+        chip.PC = 0x200;
+
+        let mut rewind = Rewind::new(1 << 20, 4, 7);
+        let mut history = Vec::new();
+        for frame in 0..30 {
+            let events = match frame {
+                5 => vec![InputEvent::KeyDown(0)],
+                12 => vec![InputEvent::KeyUp(0), InputEvent::Quit],
+                _ => vec![]
+            };
+            history.push((chip.Vx, chip.PC, chip.keypad.keys));
+            rewind.record(&chip, &events);
+            for event in events {
+                chip.keypad.handle_event(event);
+            }
+            chip.run_frame(7).unwrap();
+        }
+
+        assert_eq!(rewind.frames(), 30);
+        for expected in history.iter().rev() {
+            let chip = rewind.step_back().unwrap();
+            assert_eq!((chip.Vx, chip.PC, chip.keypad.keys), *expected);
+        }
+        assert!(rewind.step_back().is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_over_budget() {
+        let chip = Chip8::new_default();
+        let mut rewind = Rewind::new(3 * super::snapshot_size(&chip), 2, 1);
+        for _ in 0..10 {
+            rewind.record(&chip, &[]);
+        }
+        assert_eq!(rewind.frames(), 6);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{fs, path::Path, path::PathBuf};
+
+use crate::chip8::{Chip8, Chip8Display, Pixel};
+use crate::constants::{HIRES_HEIGHT, HIRES_WIDTH};
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+
+/// first bytes of every save state file
+const MAGIC: &[u8; 4] = b"CH8S";
+
+/// version of the layout written by save_state, load_state refuses any other
+pub const SAVE_STATE_VERSION: u8 = 2;
+
+/// serializes the whole machine
+/// layout, multi-byte values big-endian:
+/// magic, version, quirk flags (u16), memory size (u32) and memory, Vx, I, delay timer, sound timer,
+/// PC, SP, stack, RPL flags, display, keys (u16 bitmask), released key, waiting for key, audio
+pub fn save_state(chip: &Chip8) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(SAVE_STATE_VERSION);
+    out.extend_from_slice(&chip.quirks.to_bits().to_be_bytes());
+
+    out.extend_from_slice(&(chip.memory.len() as u32).to_be_bytes());
+    out.extend_from_slice(&chip.memory);
+    out.extend_from_slice(&chip.Vx);
+    out.extend_from_slice(&chip.I.to_be_bytes());
+    out.push(chip.delay_timer);
+    out.push(chip.sound_timer);
+    out.extend_from_slice(&chip.PC.to_be_bytes());
+    out.push(chip.SP);
+    for address in chip.stack {
+        out.extend_from_slice(&address.to_be_bytes());
+    }
+    out.extend_from_slice(&chip.rpl_flags);
+
+    //pixels are packed 8 to a byte, plane by plane
+    out.push(chip.display.hires as u8);
+    out.push(chip.display.selected_planes);
+    for plane in &chip.display.planes {
+        for row in plane {
+            for bits in row.chunks(8) {
+                out.push(bits.iter().fold(0, |byte, pixel| byte << 1 | pixel.is_on() as u8));
+            }
+        }
+    }
+
+    let keys = (0..16).fold(0u16, |mask, key| mask | (chip.keypad.keys[key] as u16) << key);
+    out.extend_from_slice(&keys.to_be_bytes());
+    out.push(chip.keypad.released.unwrap_or(0xFF));
+    out.push(chip.waiting_for_key as u8);
+
+    match chip.audio_pattern {
+        Some(pattern) => {
+            out.push(1);
+            out.extend_from_slice(&pattern);
+        },
+        None => out.push(0)
+    }
+    out.push(chip.pitch);
+    out
+}
+
+/// rebuilds a machine from save_state output
+/// the random number generator is not saved, Chip8::restore carries the running one over
+pub fn load_state(bytes: &[u8]) -> Result<Chip8, Chip8Error> {
+    let mut reader = Reader::new(bytes, Chip8Error::InvalidSaveState);
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a save state file"));
+    }
+    let version = reader.byte()?;
+    if version != SAVE_STATE_VERSION {
+        return Err(invalid(format!("save state version {} is not supported, expected {}", version, SAVE_STATE_VERSION)));
+    }
+    let quirks = Quirks::from_bits(reader.u16()?);
+    let mut chip = Chip8::new(quirks);
+
+    let memory_size = reader.u32()? as usize;
+    if memory_size != chip.memory.len() {
+        return Err(invalid(format!("memory is {} bytes, the quirks need {}", memory_size, chip.memory.len())));
+    }
+    chip.memory.copy_from_slice(reader.take(memory_size)?);
+    chip.Vx.copy_from_slice(reader.take(16)?);
+    chip.I = reader.u16()?;
+    chip.delay_timer = reader.byte()?;
+    chip.sound_timer = reader.byte()?;
+    chip.PC = reader.u16()?;
+    chip.SP = reader.byte()?;
+    if chip.SP as usize > chip.stack.len() {
+        return Err(invalid(format!("stack pointer {} is past the end of the stack", chip.SP)));
+    }
+    for address in chip.stack.iter_mut() {
+        *address = reader.u16()?;
+    }
+    chip.rpl_flags.copy_from_slice(reader.take(16)?);
+
+    chip.display = Chip8Display::new();
+    chip.display.hires = reader.byte()? != 0;
+    chip.display.selected_planes = reader.byte()? & 0x3;
+    for plane in chip.display.planes.iter_mut() {
+        let packed = reader.take(HIRES_WIDTH * HIRES_HEIGHT / 8)?;
+        for (y, row) in plane.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let byte = packed[(y * HIRES_WIDTH + x) / 8];
+                *pixel = if byte & (0x80 >> (x % 8)) != 0 { Pixel::On } else { Pixel::Off };
+            }
+        }
+    }
+
+    let keys = reader.u16()?;
+    for key in 0..16 {
+        chip.keypad.keys[key] = keys & (1 << key) != 0;
+    }
+    chip.keypad.released = match reader.byte()? {
+        0xFF => None,
+        key => Some(key & 0xF)
+    };
+    chip.waiting_for_key = reader.byte()? != 0;
+
+    if reader.byte()? != 0 {
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.take(16)?);
+        chip.audio_pattern = Some(pattern);
+    }
+    chip.pitch = reader.byte()?;
+    if !reader.at_end() {
+        return Err(invalid("trailing bytes after the save state"));
+    }
+
+    //the frontend has to catch up with everything that was restored
+    chip.draw_flag = true;
+    chip.audio_flag = chip.audio_pattern.is_some();
+    Ok(chip)
+}
+
+/// writes the machine to a save state file
+pub fn save_state_to_file(chip: &Chip8, path: &Path) -> Result<(), Chip8Error> {
+    Ok(fs::write(path, save_state(chip))?)
+}
+
+/// reads a machine back from a save state file
+pub fn load_state_from_file(path: &Path) -> Result<Chip8, Chip8Error> {
+    load_state(&fs::read(path)?)
+}
+
+/// file for a numbered slot in the working directory, slot 1 of maze.ch8 is maze.ch8.state1
+pub fn slot_path(rom: &str, slot: u8) -> PathBuf {
+    let name = Path::new(rom).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| String::from("chip8"));
+    PathBuf::from(format!("{}.state{}", name, slot))
+}
+
+fn invalid(message: impl Into<String>) -> Chip8Error {
+    Chip8Error::InvalidSaveState(message.into())
+}
+
+/// reads big-endian fields in order, running out of bytes is an error made by invalid
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    invalid: fn(String) -> Chip8Error,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], invalid: fn(String) -> Chip8Error) -> Reader<'a> {
+        Reader { bytes, position: 0, invalid }
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.bytes.len() - self.position < len {
+            return Err((self.invalid)(String::from("file is truncated")));
+        }
+        let slice = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(slice)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_state, save_state};
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn restores_every_field() {
+        let mut chip = Chip8::new(Quirks::XO_CHIP);
+        chip.memory[0xFFFF] = 0xAB;
+        chip.Vx[3] = 7;
+        chip.I = 0x1234;
+        chip.delay_timer = 9;
+        chip.sound_timer = 4;
+        chip.PC = 0x246;
+        chip.stack[0] = 0x202;
+        chip.SP = 1;
+        chip.rpl_flags[2] = 5;
+        chip.display.set_hires(true);
+        chip.display.planes[1][63][127] = crate::chip8::Pixel::On;
+        chip.keypad.press(0xA);
+        chip.keypad.press(0x3);
+        chip.keypad.release(0x3);
+        chip.audio_pattern = Some([0x55; 16]);
+        chip.pitch = 100;
+
+        let restored = load_state(&save_state(&chip)).unwrap();
+        assert_eq!(restored.memory, chip.memory);
+        assert_eq!((restored.Vx, restored.I, restored.PC, restored.SP), (chip.Vx, chip.I, chip.PC, chip.SP));
+        assert_eq!((restored.delay_timer, restored.sound_timer), (9, 4));
+        assert_eq!((restored.stack, restored.rpl_flags), (chip.stack, chip.rpl_flags));
+        assert!(restored.display.hires);
+        assert_eq!(restored.display.color(127, 63), 2);
+        assert_eq!(restored.keypad.keys, chip.keypad.keys);
+        let mut keypad = restored.keypad;
+        assert_eq!(keypad.take_released(), Some(0x3));
+        assert_eq!(restored.quirks, Quirks::XO_CHIP);
+        assert_eq!((restored.audio_pattern, restored.pitch), (chip.audio_pattern, 100));
+    }
+
+    #[test]
+    fn rejects_other_versions_and_truncated_files() {
+        let mut bytes = save_state(&Chip8::new_default());
+        assert!(matches!(load_state(&bytes[..100]), Err(Chip8Error::InvalidSaveState(_))));
+        bytes[4] += 1;
+        assert!(matches!(load_state(&bytes), Err(Chip8Error::InvalidSaveState(_))));
+        assert!(matches!(load_state(b"nope"), Err(Chip8Error::InvalidSaveState(_))));
+    }
+}
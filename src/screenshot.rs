@@ -0,0 +1,80 @@
+use std::{fs, path::Path, path::PathBuf};
+
+use crate::chip8::Chip8Display;
+use crate::error::Chip8Error;
+use crate::frontend::Palette;
+
+/// the visible part of the display as a png image
+/// every chip8 pixel becomes a scale x scale square in its palette colour, scale 0 counts as 1
+pub fn to_png(display: &Chip8Display, scale: u32, palette: &Palette) -> Result<Vec<u8>, Chip8Error> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (display.width() * scale, display.height() * scale);
+    encode_png(&indexed_pixels(display, width, height), width, height, palette)
+}
+
+/// the display stretched to width x height, one palette index per pixel, row by row
+pub fn indexed_pixels(display: &Chip8Display, width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = y * display.height() / height;
+        pixels.extend((0..width).map(|x| display.color(x * display.width() / width, row)));
+    }
+    pixels
+}
+
+/// png with the palette stored as is and pixels as indices into it
+pub(crate) fn encode_png(pixels: &[u8], width: usize, height: usize, palette: &Palette) -> Result<Vec<u8>, Chip8Error> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.concat());
+    let mut writer = encoder.write_header().map_err(image_error)?;
+    writer.write_image_data(pixels).map_err(image_error)?;
+    writer.finish().map_err(image_error)?;
+    Ok(out)
+}
+
+pub fn save_png(display: &Chip8Display, scale: u32, palette: &Palette, path: &Path) -> Result<(), Chip8Error> {
+    Ok(fs::write(path, to_png(display, scale, palette)?)?)
+}
+
+/// first file in the working directory that is not taken yet, maze.ch8 gets maze.ch8-1.png, maze.ch8-2.png, ...
+pub fn screenshot_path(rom: &str) -> PathBuf {
+    next_free_path(rom, "png")
+}
+
+/// first of rom-1.extension, rom-2.extension, ... in the working directory that does not exist
+pub fn next_free_path(rom: &str, extension: &str) -> PathBuf {
+    let name = Path::new(rom).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| String::from("chip8"));
+    (1..).map(|n| PathBuf::from(format!("{}-{}.{}", name, n, extension))).find(|path| !path.exists()).unwrap()
+}
+
+pub(crate) fn image_error(e: impl std::error::Error) -> Chip8Error {
+    Chip8Error::Image(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_png;
+    use crate::chip8::Chip8;
+    use crate::constants::DEFAULT_PALETTE;
+
+    #[test]
+    fn writes_scaled_indexed_pngs() {
+        let mut chip = Chip8::new_default();
+        chip.draw_sprite_in_mem_to_x_y(0x50, 0, 0, 1).unwrap();
+        let bytes = to_png(&chip.display, 3, &DEFAULT_PALETTE).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().palette.as_deref(), Some(&DEFAULT_PALETTE.concat()[..]));
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((frame.width, frame.height), (64 * 3, 32 * 3));
+
+        //the top row of the 0 glyph is 0xF0: four lit pixels, then dark
+        let row = &pixels[2 * frame.line_size..3 * frame.line_size];
+        assert!(row[..12].iter().all(|&p| p == 1));
+        assert!(row[12..].iter().all(|&p| p == 0));
+    }
+}
@@ -0,0 +1,279 @@
+use std::path::PathBuf;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+use sdl2::EventPump;
+
+use crate::audio::Tone;
+use crate::chip8::{Chip8, Chip8Display};
+use crate::constants::{HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, SAMPLE_RATE};
+use crate::error::Chip8Error;
+use crate::frontend::{BuzzerSink, DisplaySink, Frontend, InputEvent, KeypadSource, Palette};
+use crate::palette;
+use crate::recorder::ClipOptions;
+use crate::Config;
+
+/// host keys for the chip8 keypad, indexed by keypad value
+pub const KEYPAD_VALUES: [Keycode; 16] = [
+    Keycode::X,
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::A,
+    Keycode::S,
+    Keycode::D,
+    Keycode::Z,
+    Keycode::C,
+    Keycode::Num4,
+    Keycode::R,
+    Keycode::F,
+    Keycode::V
+];
+
+/// F1 - F9 load save state slots 1 - 9, with shift held they save them
+const SAVE_SLOT_KEYS: [Keycode; 9] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9
+];
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.fill(out);
+    }
+}
+
+/// SDL2 window canvas, the display is drawn as one texture filling the window
+pub struct SdlDisplay<'t> {
+    pub canvas: WindowCanvas,
+    pub palette: Palette,
+
+    //The palette the run started with, custom colours stay in the cycle
+    pub start_palette: Palette,
+
+    //Streaming texture of the high resolution size, the display is uploaded to its top left corner
+    pub texture: Texture<'t>,
+}
+
+impl DisplaySink for SdlDisplay<'_> {
+    fn present(&mut self, display: &Chip8Display) -> Result<(), Chip8Error> {
+        draw_display_to_window(&mut self.canvas, &mut self.texture, display, &self.palette)
+    }
+
+    fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    fn cycle_palette(&mut self) {
+        self.palette = palette::next(&self.palette, &self.start_palette);
+        println!("Palette {}", palette::name_of(&self.palette).unwrap_or("custom"));
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let state = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+        if let Err(e) = window.set_fullscreen(state) {
+            println!("Could not switch fullscreen: {}", e);
+        }
+    }
+}
+
+/// SDL2 playback device running the buzzer tone
+/// the device plays all the time, start and stop open and close the tone's gate so it can fade out
+pub struct SdlBuzzer {
+    pub device: AudioDevice<Tone>,
+}
+
+impl BuzzerSink for SdlBuzzer {
+    fn start(&mut self) {
+        self.device.lock().set_gate(true);
+    }
+
+    fn stop(&mut self) {
+        self.device.lock().set_gate(false);
+    }
+
+    fn toggle_mute(&mut self) {
+        let mut tone = self.device.lock();
+        let muted = !tone.is_muted();
+        tone.set_muted(muted);
+        println!("Sound {}", if muted { "muted" } else { "on" });
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.device.lock().set_pattern(pattern, pitch);
+    }
+}
+
+/// SDL2 event pump, keys are mapped through KEYPAD_VALUES
+pub struct SdlKeypad {
+    pub event_pump: EventPump,
+}
+
+impl KeypadSource for SdlKeypad {
+    fn poll_events(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => events.push(InputEvent::Quit),
+                Event::KeyDown { keycode: Some(Keycode::Right), .. } => events.push(InputEvent::Step),
+                Event::KeyDown { keycode: Some(Keycode::M), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => events.push(InputEvent::ToggleMute),
+                Event::KeyDown { keycode: Some(Keycode::M), .. } => events.push(InputEvent::DumpMemory),
+                Event::KeyDown { keycode: Some(Keycode::Y), .. } => events.push(InputEvent::DumpRegisters),
+                Event::KeyDown { keycode: Some(Keycode::K), .. } => events.push(InputEvent::ShowInstruction),
+                Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => events.push(InputEvent::Rewind(true)),
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => events.push(InputEvent::Rewind(false)),
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => events.push(InputEvent::Screenshot),
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => events.push(InputEvent::ToggleClip),
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => events.push(InputEvent::CyclePalette),
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => events.push(InputEvent::ToggleFullscreen),
+                Event::Window { win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed, .. } => events.push(InputEvent::Redraw),
+                Event::KeyDown { keycode: Some(k), keymod, .. } => {
+                    if let Some(slot) = save_slot(k) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            events.push(InputEvent::SaveState(slot));
+                        } else {
+                            events.push(InputEvent::LoadState(slot));
+                        }
+                    } else if let Some(key) = keypad_value(k) {
+                        events.push(InputEvent::KeyDown(key));
+                    }
+                },
+                Event::KeyUp { keycode: Some(k), .. } => {
+                    if let Some(key) = keypad_value(k) {
+                        events.push(InputEvent::KeyUp(key));
+                    }
+                },
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// save state slot for F1 - F9
+fn save_slot(keycode: Keycode) -> Option<u8> {
+    SAVE_SLOT_KEYS.iter().position(|&key| key == keycode).map(|i| i as u8 + 1)
+}
+
+/// chip8 keypad value bound to a host key, if any
+fn keypad_value(keycode: Keycode) -> Option<u8> {
+    KEYPAD_VALUES.iter().position(|&key| key == keycode).map(|i| i as u8)
+}
+
+/// open sdl2 window and run the rom in it
+/// the display is presented at most once per 60Hz frame
+pub fn open_window(mut cfg: Config) -> Result<(), Chip8Error> {
+    let movie = cfg.movie_mode()?;
+    let Config {filename, is_debug, instructions_per_frame, quirks, seed, clip, clip_scale, wav, tone, mute, palette, screenshot_scale, scale, integer_scale, fullscreen, ..} = cfg;
+
+    let sdl_context = sdl2::init().map_err(Chip8Error::Frontend)?;
+    let video_subsystem = sdl_context.video().map_err(Chip8Error::Frontend)?;
+    let audio_subsystem = sdl_context.audio().map_err(Chip8Error::Frontend)?;
+
+    let desired_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),  // mono
+        samples: None       // default sample size
+    };
+
+    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+        let mut buzzer = Tone::new(spec.freq as f32, tone);
+        buzzer.set_muted(mute);
+        buzzer
+    }).map_err(Chip8Error::Frontend)?;
+    device.resume();
+
+    let mut window = video_subsystem.window("Chip-8 emulator", LORES_WIDTH as u32 * scale, LORES_HEIGHT as u32 * scale);
+    window.position_centered().resizable();
+    if fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(|e| Chip8Error::Frontend(e.to_string()))?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| Chip8Error::Frontend(e.to_string()))?;
+    canvas.set_integer_scale(integer_scale).map_err(Chip8Error::Frontend)?;
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    let event_pump = sdl_context.event_pump().map_err(Chip8Error::Frontend)?;
+
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, HIRES_WIDTH as u32, HIRES_HEIGHT as u32)
+        .map_err(|e| Chip8Error::Frontend(e.to_string()))?;
+    let mut display = SdlDisplay { canvas, palette, start_palette: palette, texture };
+    let mut buzzer = SdlBuzzer { device };
+    let mut keypad = SdlKeypad { event_pump };
+    let mut frontend = Frontend {
+        display: &mut display,
+        buzzer: &mut buzzer,
+        keypad: &mut keypad,
+    };
+
+    let mut my_chip8 = Chip8::new(quirks);
+    if let Some(seed) = seed {
+        my_chip8.seed(seed);
+    }
+    let clip = ClipOptions { path: clip.map(PathBuf::from), scale: clip_scale, audio: wav.map(PathBuf::from), tone, screenshot_scale };
+    my_chip8.start_device(&filename, is_debug, instructions_per_frame, movie, clip, &mut frontend)?;
+
+    if !is_debug {
+        println!("Execution finished, press space to leave");
+        //blocks until the next event instead of polling
+        loop {
+            match keypad.event_pump.wait_event() {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Space), .. } => break,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// draws the pixels of Chip8Display to SDL2 canvas
+/// the pixels are uploaded to texture in one go and copied to the canvas with a single present
+/// the canvas' logical size follows the display resolution, so SDL2 scales every pixel up to fill the window
+/// and letterboxes it in the background colour when the window doesn't have the display's aspect ratio
+pub fn draw_display_to_window(canvas: &mut WindowCanvas, texture: &mut Texture, display: &Chip8Display, palette: &Palette) -> Result<(), Chip8Error> {
+    let (width, height) = (display.width(), display.height());
+    if canvas.logical_size() != (width as u32, height as u32) {
+        canvas.set_logical_size(width as u32, height as u32).map_err(|e| Chip8Error::Frontend(e.to_string()))?;
+    }
+    let area = Rect::new(0, 0, width as u32, height as u32);
+    texture.with_lock(area, |buffer, pitch| {
+        for y in 0..height {
+            let row = &mut buffer[y * pitch..y * pitch + width * 3];
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                pixel.copy_from_slice(&palette[display.color(x, y) as usize]);
+            }
+        }
+    }).map_err(Chip8Error::Frontend)?;
+
+    let [r, g, b] = palette[0];
+    canvas.set_draw_color(Color::RGB(r, g, b));
+    canvas.clear();
+    canvas.copy(texture, area, None).map_err(Chip8Error::Frontend)?;
+    canvas.present();
+    Ok(())
+}